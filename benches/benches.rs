@@ -3,166 +3,179 @@
 extern crate test;
 extern crate lore;
 
-use self::test::Bencher;
+use std::sync::Arc;
 
-use lore::runtime::bytecode::*;
-use lore::runtime::context::*;
-use lore::runtime::function::*;
-use lore::runtime::environment::*;
+use self::test::Bencher;
 
+use lore::bytecode::*;
+use lore::context::*;
+use lore::cst::*;
+use lore::function::*;
+use lore::environment::*;
 
-#[bench]
-fn add_cst_repeat(bencher: &mut Bencher) {
-    let add_cst_repeat_instructions = vec![
-        Instruction::Load(0),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-        Instruction::Cst(0),
-        Instruction::Add(Type::U64),
-    ];
-
-    let add_cst_repeat_constants = ConstantTable::new(vec![
-        Constant::U64(20),
-    ]);
-
-    let add_cst_repeat = Function::new(
-        "add_cst_repeat".to_string(),
-        add_cst_repeat_constants,
-        add_cst_repeat_instructions,
-        1,
-    );
 
+/// Registers the function and runs it with the engine on every iteration.
+/// Fetching the function resolves its constants and specializes its
+/// arithmetic, so even the Switch engine runs the lowered instructions.
+fn bench_function(bencher: &mut Bencher, function: Function, arguments: Vec<u64>, engine: Engine) {
     let mut environment = Environment::new();
-    let add_cst_repeat_ref = environment.register_function(add_cst_repeat);
+    let id = environment.register_function(function);
+    let function = environment.fetch_function_by_id(id).unwrap();
 
-    let context = Context::new(1024);
-    let arguments = vec![5];
+    let mut context = Context::new(1024);
+    context.set_engine(engine);
     bencher.iter(|| {
-        context.run(add_cst_repeat_ref, &arguments);
+        context.run_raw(function, &arguments).unwrap()
     });
 }
 
-#[bench]
-fn dup_repeat(bencher: &mut Bencher) {
-    let dup_repeat_instructions = vec![
-        Instruction::Load(0),
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-        Instruction::Dup,
-    ];
-
-    let dup_repeat_constants = ConstantTable::new(vec![
-        Constant::U64(20),
-    ]);
-
-    let dup_repeat = Function::new(
-        "dup_repeat".to_string(),
-        dup_repeat_constants,
-        dup_repeat_instructions,
-        1,
-    );
-
+/// Runs the function without fetching it, so that the Switch engine matches
+/// on the typed instructions and looks up each constant in the table. This is
+/// the baseline the pre-decoded and specialized engines are compared with.
+fn bench_unresolved(bencher: &mut Bencher, function: Function, arguments: Vec<u64>) {
     let mut environment = Environment::new();
-    let dup_repeat_ref = environment.register_function(dup_repeat);
+    let id = environment.register_function(function);
+    let function = environment.get_function_by_id(id);
 
-    let context = Context::new(1024);
-    let arguments = vec![5];
+    let mut context = Context::new(1024);
+    context.set_engine(Engine::Switch);
     bencher.iter(|| {
-        context.run(dup_repeat_ref, &arguments);
+        context.run_raw(function, &arguments).unwrap()
     });
 }
 
-#[bench]
-fn nop_repeat(bencher: &mut Bencher) {
-    let nop_repeat_instructions = vec![
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-        Instruction::Nop,
-    ];
-
-    let nop_repeat = Function::new(
+fn add_cst_repeat() -> Function {
+    let mut instructions = vec![Instruction::Load(0)];
+    for _ in 0..100 {
+        instructions.push(Instruction::Cst(0));
+        instructions.push(Instruction::Add(Type::U64));
+    }
+    instructions.push(Instruction::Ret(1));
+
+    Function::new(
+        "add_cst_repeat".to_string(),
+        Sizes::new(1, 1, 1, 2),
+        Arc::new(ConstantTable::new(vec![
+            Constant::U64(20),
+        ])),
+        Instructions::Bytecode(instructions),
+    )
+}
+
+/// Accumulates into a local, which the register engine does without
+/// going through the operand stack.
+fn local_accumulate() -> Function {
+    let mut instructions = vec![];
+    for _ in 0..100 {
+        instructions.push(Instruction::Load(1));
+        instructions.push(Instruction::Load(0));
+        instructions.push(Instruction::Add(Type::U64));
+        instructions.push(Instruction::Store(1));
+    }
+    instructions.push(Instruction::Load(1));
+    instructions.push(Instruction::Ret(1));
+
+    Function::new(
+        "local_accumulate".to_string(),
+        Sizes::new(1, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(instructions),
+    )
+}
+
+fn dup_repeat() -> Function {
+    let mut instructions = vec![Instruction::Load(0)];
+    for _ in 0..200 {
+        instructions.push(Instruction::Dup);
+    }
+
+    Function::new(
+        "dup_repeat".to_string(),
+        Sizes::new(0, 1, 1, 201),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(instructions),
+    )
+}
+
+fn nop_repeat() -> Function {
+    Function::new(
         "nop_repeat".to_string(),
-        ConstantTable::new(vec![]),
-        nop_repeat_instructions,
-        0,
-    );
+        Sizes::new(0, 0, 0, 0),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode((0..201).map(|_| Instruction::Nop).collect()),
+    )
+}
 
-    let mut environment = Environment::new();
-    let nop_repeat_ref = environment.register_function(nop_repeat);
+fn do_nothing() -> Function {
+    Function::new(
+        "do_nothing".to_string(),
+        Sizes::new(0, 0, 0, 0),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![]),
+    )
+}
 
-    let context = Context::new(1024);
-    let arguments = vec![];
-    bencher.iter(|| {
-        context.run(nop_repeat_ref, &arguments);
-    });
+#[bench]
+fn add_cst_repeat_threaded(bencher: &mut Bencher) {
+    bench_function(bencher, add_cst_repeat(), vec![5], Engine::Threaded);
 }
 
 #[bench]
-fn overhead(bencher: &mut Bencher) {
-    let do_nothing = Function::new(
-        "do_nothing".to_string(),
-        ConstantTable::new(vec![]),
-        vec![],
-        0,
-    );
+fn add_cst_repeat_switch(bencher: &mut Bencher) {
+    bench_function(bencher, add_cst_repeat(), vec![5], Engine::Switch);
+}
 
-    let mut environment = Environment::new();
-    let do_nothing_ref = environment.register_function(do_nothing);
+#[bench]
+fn add_cst_repeat_unresolved(bencher: &mut Bencher) {
+    bench_unresolved(bencher, add_cst_repeat(), vec![5]);
+}
 
-    let context = Context::new(1024);
-    let arguments = vec![];
-    bencher.iter(|| {
-        context.run(do_nothing_ref, &arguments);
-    });
+#[bench]
+fn add_cst_repeat_register(bencher: &mut Bencher) {
+    bench_function(bencher, add_cst_repeat(), vec![5], Engine::Register);
+}
+
+#[bench]
+fn local_accumulate_threaded(bencher: &mut Bencher) {
+    bench_function(bencher, local_accumulate(), vec![5, 0], Engine::Threaded);
+}
+
+#[bench]
+fn local_accumulate_switch(bencher: &mut Bencher) {
+    bench_function(bencher, local_accumulate(), vec![5, 0], Engine::Switch);
+}
+
+#[bench]
+fn local_accumulate_unresolved(bencher: &mut Bencher) {
+    bench_unresolved(bencher, local_accumulate(), vec![5, 0]);
+}
+
+#[bench]
+fn local_accumulate_register(bencher: &mut Bencher) {
+    bench_function(bencher, local_accumulate(), vec![5, 0], Engine::Register);
+}
+
+#[bench]
+fn dup_repeat_threaded(bencher: &mut Bencher) {
+    bench_function(bencher, dup_repeat(), vec![5], Engine::Threaded);
+}
+
+#[bench]
+fn dup_repeat_switch(bencher: &mut Bencher) {
+    bench_function(bencher, dup_repeat(), vec![5], Engine::Switch);
+}
+
+#[bench]
+fn nop_repeat_threaded(bencher: &mut Bencher) {
+    bench_function(bencher, nop_repeat(), vec![], Engine::Threaded);
+}
+
+#[bench]
+fn nop_repeat_switch(bencher: &mut Bencher) {
+    bench_function(bencher, nop_repeat(), vec![], Engine::Switch);
+}
+
+#[bench]
+fn overhead(bencher: &mut Bencher) {
+    bench_function(bencher, do_nothing(), vec![], Engine::Threaded);
 }
@@ -1,15 +1,15 @@
 use std::fmt;
-use std::io::{Read, Result};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 
-use num::FromPrimitive;
+use num::{BigInt, FromPrimitive};
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use io;
 
 
 enum_from_primitive! {
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Type {
     I8      = 0x0,
     I16     = 0x1,
@@ -23,14 +23,19 @@ pub enum Type {
     F64     = 0x9,
     Ptr     = 0xA,
     Void    = 0xB,
+    BigInt  = 0xC,
 }
 }
 
 pub type VariableIndex = u16;
 pub type ConstantTableIndex = u16;
+pub type NamedTypeId = u32;
+
+/// The number of opcodes. Must be kept in sync with Opcode.
+pub const OPCODE_COUNT: usize = 0x14;
 
 enum_from_primitive! {
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Opcode {
     Nop = 0x00,
     Pop = 0x01,
@@ -44,9 +49,18 @@ pub enum Opcode {
     Div = 0x09,
     Ret = 0x0A,
     Print = 0x0B,
+    IsInstance = 0x0C,
+    Cast = 0x0D,
+    Rem = 0x0E,
+    Cmp = 0x0F,
+    Conv = 0x10,
+    PrintFmt = 0x11,
+    Assert = 0x12,
+    Abort = 0x13,
 }
 }
 
+#[derive(Clone, Copy)]
 pub enum Instruction {
     Nop,
     Pop,
@@ -60,6 +74,59 @@ pub enum Instruction {
     Div(Type),
     Ret(u8), // u8: The number of elements on the stack that are returned.
     Print(Type),
+
+    // Type tests. Values do not carry their type yet, so the operand of both
+    // is a type ID that the bytecode pushed, not a value whose type is looked
+    // up. IsInstance replaces the ID with 1 if it names a subtype of the given
+    // type and with 0 otherwise. Cast keeps the ID or fails. IDs that are not
+    // registered, including slots beyond the u32 range, are no subtypes.
+    IsInstance(NamedTypeId),
+    Cast(NamedTypeId),
+    Rem(Type),
+    Cmp(Type), // Pushes -1, 0 or 1 as an i32.
+    Conv(Type, Type), // From the first type to the second type.
+    PrintFmt(ConstantTableIndex, u8), // The Str template and the number of arguments.
+    Assert(ConstantTableIndex), // Pops an i32 condition. The Str message is used if it is 0.
+    Abort(ConstantTableIndex), // The Str message.
+
+    // A Cst whose constant has been resolved into the raw bits of a stack
+    // slot by Function::resolve_constants when the bytecode is loaded. The
+    // index is kept for disassembly. Not part of the disk format.
+    CstBits(ConstantTableIndex, u64),
+
+    // Monomorphic arithmetic. These are not part of the disk format, but
+    // lowered from the typed instructions by Instruction::specialize when
+    // the bytecode is loaded.
+    AddU64,
+    AddU32,
+    AddI64,
+    AddI32,
+    AddF64,
+    AddF32,
+    SubU64,
+    SubU32,
+    SubI64,
+    SubI32,
+    SubF64,
+    SubF32,
+    MulU64,
+    MulU32,
+    MulI64,
+    MulI32,
+    MulF64,
+    MulF32,
+    DivU64,
+    DivU32,
+    DivI64,
+    DivI32,
+    DivF64,
+    DivF32,
+    RemU64,
+    RemU32,
+    RemI64,
+    RemI32,
+    RemF64,
+    RemF32,
 }
 
 enum_from_primitive! {
@@ -72,17 +139,34 @@ pub enum ConstantTag {
     F32 = 0x04,
     F64 = 0x05,
     Str = 0x06,
+    BigInt = 0x07,
+    I8 = 0x08,
+    I16 = 0x09,
+    U8 = 0x0A,
+    U16 = 0x0B,
+    Char = 0x0C,
+    Bytes = 0x0D,
 }
 }
 
 pub enum Constant {
+    I8(i8),
+    I16(i16),
     I32(i32),
     I64(i64),
+    U8(u8),
+    U16(u16),
     U32(u32),
     U64(u64),
     F32(f32),
     F64(f64),
     Str(String),
+    BigInt(BigInt),
+    Char(char), // Pushed as its u32 code point.
+    /// A binary blob. No instruction consumes blobs yet: 'cst' rejects them
+    /// when the function is fetched, so they are only reachable by index
+    /// from future instructions.
+    Bytes(Vec<u8>),
 }
 
 
@@ -91,17 +175,227 @@ impl Type {
         let value = try!(read.read_u8());
         match Type::from_u8(value) {
             Some(t) => Ok(t),
-            None => panic!("Type tag not {} known.", value),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("Unknown type tag: {}", value))),
         }
     }
 }
 
 impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        match *self {
+            Instruction::Nop => Opcode::Nop,
+            Instruction::Pop => Opcode::Pop,
+            Instruction::Dup => Opcode::Dup,
+            Instruction::Cst(..) | Instruction::CstBits(..) => Opcode::Cst,
+            Instruction::Load(..) => Opcode::Load,
+            Instruction::Store(..) => Opcode::Store,
+            Instruction::Add(..) => Opcode::Add,
+            Instruction::Sub(..) => Opcode::Sub,
+            Instruction::Mul(..) => Opcode::Mul,
+            Instruction::Div(..) => Opcode::Div,
+            Instruction::Ret(..) => Opcode::Ret,
+            Instruction::Print(..) => Opcode::Print,
+            Instruction::IsInstance(..) => Opcode::IsInstance,
+            Instruction::Cast(..) => Opcode::Cast,
+            Instruction::Rem(..) => Opcode::Rem,
+            Instruction::Cmp(..) => Opcode::Cmp,
+            Instruction::Conv(..) => Opcode::Conv,
+            Instruction::PrintFmt(..) => Opcode::PrintFmt,
+            Instruction::Assert(..) => Opcode::Assert,
+            Instruction::Abort(..) => Opcode::Abort,
+            Instruction::AddU64 => Opcode::Add,
+            Instruction::AddU32 => Opcode::Add,
+            Instruction::AddI64 => Opcode::Add,
+            Instruction::AddI32 => Opcode::Add,
+            Instruction::AddF64 => Opcode::Add,
+            Instruction::AddF32 => Opcode::Add,
+            Instruction::SubU64 => Opcode::Sub,
+            Instruction::SubU32 => Opcode::Sub,
+            Instruction::SubI64 => Opcode::Sub,
+            Instruction::SubI32 => Opcode::Sub,
+            Instruction::SubF64 => Opcode::Sub,
+            Instruction::SubF32 => Opcode::Sub,
+            Instruction::MulU64 => Opcode::Mul,
+            Instruction::MulU32 => Opcode::Mul,
+            Instruction::MulI64 => Opcode::Mul,
+            Instruction::MulI32 => Opcode::Mul,
+            Instruction::MulF64 => Opcode::Mul,
+            Instruction::MulF32 => Opcode::Mul,
+            Instruction::DivU64 => Opcode::Div,
+            Instruction::DivU32 => Opcode::Div,
+            Instruction::DivI64 => Opcode::Div,
+            Instruction::DivI32 => Opcode::Div,
+            Instruction::DivF64 => Opcode::Div,
+            Instruction::DivF32 => Opcode::Div,
+            Instruction::RemU64 => Opcode::Rem,
+            Instruction::RemU32 => Opcode::Rem,
+            Instruction::RemI64 => Opcode::Rem,
+            Instruction::RemI32 => Opcode::Rem,
+            Instruction::RemF64 => Opcode::Rem,
+            Instruction::RemF32 => Opcode::Rem,
+        }
+    }
+
+    /// The number of operands that the instruction pops and pushes.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match *self {
+            Instruction::Nop | Instruction::Ret(..) | Instruction::Abort(..) => (0, 0),
+            Instruction::Pop | Instruction::Store(..) | Instruction::Print(..) |
+            Instruction::Assert(..) => (1, 0),
+            Instruction::Dup => (1, 2),
+            Instruction::Cst(..) | Instruction::CstBits(..) | Instruction::Load(..) => (0, 1),
+            Instruction::IsInstance(..) | Instruction::Cast(..) | Instruction::Conv(..) => (1, 1),
+            Instruction::PrintFmt(_, argc) => (argc as usize, 0),
+            Instruction::Add(..) | Instruction::Sub(..) | Instruction::Mul(..) |
+            Instruction::Div(..) | Instruction::Rem(..) | Instruction::Cmp(..) => (2, 1),
+            Instruction::AddU64 |
+            Instruction::AddU32 |
+            Instruction::AddI64 |
+            Instruction::AddI32 |
+            Instruction::AddF64 |
+            Instruction::AddF32 |
+            Instruction::SubU64 |
+            Instruction::SubU32 |
+            Instruction::SubI64 |
+            Instruction::SubI32 |
+            Instruction::SubF64 |
+            Instruction::SubF32 |
+            Instruction::MulU64 |
+            Instruction::MulU32 |
+            Instruction::MulI64 |
+            Instruction::MulI32 |
+            Instruction::MulF64 |
+            Instruction::MulF32 |
+            Instruction::DivU64 |
+            Instruction::DivU32 |
+            Instruction::DivI64 |
+            Instruction::DivI32 |
+            Instruction::DivF64 |
+            Instruction::DivF32 |
+            Instruction::RemU64 |
+            Instruction::RemU32 |
+            Instruction::RemI64 |
+            Instruction::RemI32 |
+            Instruction::RemF64 |
+            Instruction::RemF32 => (2, 1),
+        }
+    }
+
+    /// Lowers typed arithmetic into its monomorphic form. Other instructions,
+    /// including arithmetic on big integers, are returned as they are.
+    pub fn specialize(&self) -> Instruction {
+        match *self {
+            Instruction::Add(Type::U64) => Instruction::AddU64,
+            Instruction::Add(Type::U32) => Instruction::AddU32,
+            Instruction::Add(Type::I64) => Instruction::AddI64,
+            Instruction::Add(Type::I32) => Instruction::AddI32,
+            Instruction::Add(Type::F64) => Instruction::AddF64,
+            Instruction::Add(Type::F32) => Instruction::AddF32,
+            Instruction::Sub(Type::U64) => Instruction::SubU64,
+            Instruction::Sub(Type::U32) => Instruction::SubU32,
+            Instruction::Sub(Type::I64) => Instruction::SubI64,
+            Instruction::Sub(Type::I32) => Instruction::SubI32,
+            Instruction::Sub(Type::F64) => Instruction::SubF64,
+            Instruction::Sub(Type::F32) => Instruction::SubF32,
+            Instruction::Mul(Type::U64) => Instruction::MulU64,
+            Instruction::Mul(Type::U32) => Instruction::MulU32,
+            Instruction::Mul(Type::I64) => Instruction::MulI64,
+            Instruction::Mul(Type::I32) => Instruction::MulI32,
+            Instruction::Mul(Type::F64) => Instruction::MulF64,
+            Instruction::Mul(Type::F32) => Instruction::MulF32,
+            Instruction::Div(Type::U64) => Instruction::DivU64,
+            Instruction::Div(Type::U32) => Instruction::DivU32,
+            Instruction::Div(Type::I64) => Instruction::DivI64,
+            Instruction::Div(Type::I32) => Instruction::DivI32,
+            Instruction::Div(Type::F64) => Instruction::DivF64,
+            Instruction::Div(Type::F32) => Instruction::DivF32,
+            Instruction::Rem(Type::U64) => Instruction::RemU64,
+            Instruction::Rem(Type::U32) => Instruction::RemU32,
+            Instruction::Rem(Type::I64) => Instruction::RemI64,
+            Instruction::Rem(Type::I32) => Instruction::RemI32,
+            Instruction::Rem(Type::F64) => Instruction::RemF64,
+            Instruction::Rem(Type::F32) => Instruction::RemF32,
+            inst => inst,
+        }
+    }
+
+    /// The form in which a lowered instruction is stored on disk.
+    pub fn generic(&self) -> Instruction {
+        match *self {
+            Instruction::CstBits(index, _) => Instruction::Cst(index),
+            Instruction::AddU64 => Instruction::Add(Type::U64),
+            Instruction::AddU32 => Instruction::Add(Type::U32),
+            Instruction::AddI64 => Instruction::Add(Type::I64),
+            Instruction::AddI32 => Instruction::Add(Type::I32),
+            Instruction::AddF64 => Instruction::Add(Type::F64),
+            Instruction::AddF32 => Instruction::Add(Type::F32),
+            Instruction::SubU64 => Instruction::Sub(Type::U64),
+            Instruction::SubU32 => Instruction::Sub(Type::U32),
+            Instruction::SubI64 => Instruction::Sub(Type::I64),
+            Instruction::SubI32 => Instruction::Sub(Type::I32),
+            Instruction::SubF64 => Instruction::Sub(Type::F64),
+            Instruction::SubF32 => Instruction::Sub(Type::F32),
+            Instruction::MulU64 => Instruction::Mul(Type::U64),
+            Instruction::MulU32 => Instruction::Mul(Type::U32),
+            Instruction::MulI64 => Instruction::Mul(Type::I64),
+            Instruction::MulI32 => Instruction::Mul(Type::I32),
+            Instruction::MulF64 => Instruction::Mul(Type::F64),
+            Instruction::MulF32 => Instruction::Mul(Type::F32),
+            Instruction::DivU64 => Instruction::Div(Type::U64),
+            Instruction::DivU32 => Instruction::Div(Type::U32),
+            Instruction::DivI64 => Instruction::Div(Type::I64),
+            Instruction::DivI32 => Instruction::Div(Type::I32),
+            Instruction::DivF64 => Instruction::Div(Type::F64),
+            Instruction::DivF32 => Instruction::Div(Type::F32),
+            Instruction::RemU64 => Instruction::Rem(Type::U64),
+            Instruction::RemU32 => Instruction::Rem(Type::U32),
+            Instruction::RemI64 => Instruction::Rem(Type::I64),
+            Instruction::RemI32 => Instruction::Rem(Type::I32),
+            Instruction::RemF64 => Instruction::Rem(Type::F64),
+            Instruction::RemF32 => Instruction::Rem(Type::F32),
+            inst => inst,
+        }
+    }
+
+    /// Writes the instruction in the format that from_read reads. Resolved
+    /// and specialized instructions are written in their generic form.
+    pub fn write(&self, write: &mut Write) -> Result<()> {
+        let generic = self.generic();
+        try!(write.write_u8(generic.opcode() as u8));
+        match generic {
+            Instruction::Nop | Instruction::Pop | Instruction::Dup => { },
+            Instruction::Cst(index) | Instruction::Assert(index) | Instruction::Abort(index) => {
+                try!(write.write_u16::<BigEndian>(index));
+            },
+            Instruction::Load(var) | Instruction::Store(var) => {
+                try!(write.write_u16::<BigEndian>(var));
+            },
+            Instruction::Add(t) | Instruction::Sub(t) | Instruction::Mul(t) | Instruction::Div(t) |
+            Instruction::Rem(t) | Instruction::Cmp(t) | Instruction::Print(t) => {
+                try!(write.write_u8(t as u8));
+            },
+            Instruction::Ret(count) => try!(write.write_u8(count)),
+            Instruction::IsInstance(id) | Instruction::Cast(id) => {
+                try!(write.write_u32::<BigEndian>(id));
+            },
+            Instruction::Conv(from, to) => {
+                try!(write.write_u8(from as u8));
+                try!(write.write_u8(to as u8));
+            },
+            Instruction::PrintFmt(index, argc) => {
+                try!(write.write_u16::<BigEndian>(index));
+                try!(write.write_u8(argc));
+            },
+            inst => unreachable!("{:?} has no generic form.", inst),
+        }
+        Ok(())
+    }
+
     pub fn from_read(read: &mut Read) -> Result<Instruction> {
         let opcode = try!(read.read_u8());
         let opcode = match Opcode::from_u8(opcode) {
             Some(opcode) => opcode,
-            None => panic!("Invalid opcode: {}", opcode),
+            None => return Err(Error::new(ErrorKind::InvalidData, format!("Invalid opcode: {}", opcode))),
         };
 
         let instruction = match opcode {
@@ -144,6 +438,40 @@ impl Instruction {
                 let t = try!(Type::from_read(read));
                 Instruction::Print(t)
             },
+            Opcode::IsInstance => {
+                let id = try!(read.read_u32::<BigEndian>()) as NamedTypeId;
+                Instruction::IsInstance(id)
+            },
+            Opcode::Cast => {
+                let id = try!(read.read_u32::<BigEndian>()) as NamedTypeId;
+                Instruction::Cast(id)
+            },
+            Opcode::Rem => {
+                let t = try!(Type::from_read(read));
+                Instruction::Rem(t)
+            },
+            Opcode::Cmp => {
+                let t = try!(Type::from_read(read));
+                Instruction::Cmp(t)
+            },
+            Opcode::Conv => {
+                let from = try!(Type::from_read(read));
+                let to = try!(Type::from_read(read));
+                Instruction::Conv(from, to)
+            },
+            Opcode::PrintFmt => {
+                let index = try!(read.read_u16::<BigEndian>()) as ConstantTableIndex;
+                let argc = try!(read.read_u8());
+                Instruction::PrintFmt(index, argc)
+            },
+            Opcode::Assert => {
+                let index = try!(read.read_u16::<BigEndian>()) as ConstantTableIndex;
+                Instruction::Assert(index)
+            },
+            Opcode::Abort => {
+                let index = try!(read.read_u16::<BigEndian>()) as ConstantTableIndex;
+                Instruction::Abort(index)
+            },
         };
 
         Ok(instruction)
@@ -155,10 +483,21 @@ impl Constant {
         let constant_tag = try!(read.read_u8());
         let constant_tag: ConstantTag = match ConstantTag::from_u8(constant_tag) {
             Some(tag) => tag,
-            None => panic!("Invalid constant tag: {}", constant_tag),
+            None => return Err(Error::new(ErrorKind::InvalidData,
+                format!("Invalid constant tag: {}", constant_tag))),
         };
 
         match constant_tag {
+            ConstantTag::I8 => {
+                let value = try!(read.read_i8());
+                Ok(Constant::I8(value))
+            },
+
+            ConstantTag::I16 => {
+                let value = try!(read.read_i16::<BigEndian>());
+                Ok(Constant::I16(value))
+            },
+
             ConstantTag::I32 => {
                 let value = try!(read.read_i32::<BigEndian>());
                 Ok(Constant::I32(value))
@@ -169,6 +508,16 @@ impl Constant {
                 Ok(Constant::I64(value))
             },
 
+            ConstantTag::U8 => {
+                let value = try!(read.read_u8());
+                Ok(Constant::U8(value))
+            },
+
+            ConstantTag::U16 => {
+                let value = try!(read.read_u16::<BigEndian>());
+                Ok(Constant::U16(value))
+            },
+
             ConstantTag::U32 => {
                 let value = try!(read.read_u32::<BigEndian>());
                 Ok(Constant::U32(value))
@@ -193,6 +542,28 @@ impl Constant {
                 let string = try!(io::read_string(read));
                 Ok(Constant::Str(string))
             },
+
+            // Big integers are stored as big-endian two's complement bytes,
+            // which may be longer than 64 KiB, like byte blobs.
+            ConstantTag::BigInt => {
+                let bytes = try!(io::read_blob(read));
+                Ok(Constant::BigInt(BigInt::from_signed_bytes_be(&bytes[..])))
+            },
+
+            ConstantTag::Char => {
+                let value = try!(read.read_u32::<BigEndian>());
+                match char::from_u32(value) {
+                    Some(c) => Ok(Constant::Char(c)),
+                    None => Err(Error::new(ErrorKind::InvalidData,
+                        format!("Invalid char constant: {}", value))),
+                }
+            },
+
+            // Unlike strings, byte blobs may be longer than 64 KiB.
+            ConstantTag::Bytes => {
+                let bytes = try!(io::read_blob(read));
+                Ok(Constant::Bytes(bytes))
+            },
         }
     }
 }
@@ -213,6 +584,7 @@ impl fmt::Debug for Type {
             Type::F64  => write!(f, "f64"),
             Type::Ptr  => write!(f, "ptr"),
             Type::Void => write!(f, "void"),
+            Type::BigInt => write!(f, "bigint"),
         }
     }
 }
@@ -232,6 +604,17 @@ impl fmt::Debug for Instruction {
             Instruction::Div(ref t) => write!(f, "div[{:?}]", t),
             Instruction::Ret(ref count) => write!(f, "ret({:?})", count),
             Instruction::Print(ref t) => write!(f, "print[{:?}]", t),
+            Instruction::IsInstance(ref id) => write!(f, "isinstance[type id] @{:?}", id),
+            Instruction::Cast(ref id) => write!(f, "cast[type id] @{:?}", id),
+            Instruction::Rem(ref t) => write!(f, "rem[{:?}]", t),
+            Instruction::Cmp(ref t) => write!(f, "cmp[{:?}]", t),
+            Instruction::Conv(ref from, ref to) => write!(f, "conv[{:?} -> {:?}]", from, to),
+            Instruction::PrintFmt(ref index, ref argc) => write!(f, "printfmt #{:?}({:?})", index, argc),
+            Instruction::Assert(ref index) => write!(f, "assert #{:?}", index),
+            Instruction::Abort(ref index) => write!(f, "abort #{:?}", index),
+
+            // Disassembles like the typed form.
+            _ => write!(f, "{:?}", self.generic()),
         }
     }
 }
@@ -239,13 +622,20 @@ impl fmt::Debug for Instruction {
 impl fmt::Debug for Constant {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Constant::I8(ref num) => write!(f, "i8: {}", num),
+            Constant::I16(ref num) => write!(f, "i16: {}", num),
             Constant::I32(ref num) => write!(f, "i32: {}", num),
             Constant::I64(ref num) => write!(f, "i64: {}", num),
+            Constant::U8(ref num) => write!(f, "u8: {}", num),
+            Constant::U16(ref num) => write!(f, "u16: {}", num),
             Constant::U32(ref num) => write!(f, "u32: {}", num),
             Constant::U64(ref num) => write!(f, "u64: {}", num),
             Constant::F32(ref num) => write!(f, "f32: {}", num),
             Constant::F64(ref num) => write!(f, "f64: {}", num),
             Constant::Str(ref val) => write!(f, "str: '{}'", val),
+            Constant::BigInt(ref num) => write!(f, "bigint: {}", num),
+            Constant::Char(ref c) => write!(f, "char: {:?}", c),
+            Constant::Bytes(ref bytes) => write!(f, "bytes: {:?}", bytes),
         }
     }
 }
@@ -1,22 +1,200 @@
 extern crate alloc;
 
+use std::cmp::{self, Ordering};
 use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use bytecode::*;
+use debugger::Debugger;
+use environment::Environment;
+use error::{Fault, StackTrace, TraceFrame, Trap, VmError};
+use format::{Segment, Template};
+use fuel::FuelCosts;
 use function::*;
+use heap::Heap;
+use hierarchy::TypeHierarchy;
+use interrupt::InterruptHandle;
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+use jit::Jit;
+use memory::MemoryUsage;
+use observer::ExecutionObserver;
+use profiler::{ProfileReport, Profiler};
+use snapshot::{FrameSnapshot, Snapshot};
+use value::{IntoValues, Value};
 
-use self::alloc::heap::{allocate, deallocate};
+use num::{BigInt, FromPrimitive, ToPrimitive, Zero};
+
+use self::alloc::heap::{allocate, deallocate, reallocate};
 
 
 const STACK_ALIGN: usize = 8;
 const STACK_ELEMENT_SIZE: usize = 8;
 
+/// The outcome of a single step.
+#[derive(Debug)]
+pub enum Step {
+    /// The function has more instructions to execute.
+    Paused,
+
+    /// The function has finished with the given results.
+    Finished(Vec<u64>),
+}
+
+/// How the fast path executes instructions. Metered, stepped and
+/// instrumented execution always matches on the instructions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    /// Matches on each instruction.
+    Switch,
+
+    /// Calls the handlers of the pre-decoded ThreadedCode of the function,
+    /// which Environment::fetch_function_by_id translates. Falls back to
+    /// Switch for functions without threaded code.
+    Threaded,
+
+    /// Executes the RegisterCode of the function, which is also translated
+    /// by Environment::fetch_function_by_id. Falls back to Switch for
+    /// functions without register code and for resumed executions.
+    Register,
+}
+
+/// Instructions decoded into handlers with resolved operands.
+pub struct ThreadedCode {
+    ops: Vec<ThreadedOp>,
+}
+
+/// Bytecode translated for a register machine. Registers are the stack
+/// elements of a frame, starting at its bottom, so that the locals come
+/// first and the operand stack slots follow. Loads, stores and dups merely
+/// rename registers, and arithmetic reads and writes registers directly.
+/// Other instructions execute on the operand stack, which is brought up to
+/// date before them.
+pub struct RegisterCode {
+    ops: Vec<RegisterOp>,
+
+    /// The number of instructions that were translated.
+    inst_count: usize,
+}
+
+type RegisterHandler = unsafe fn(&mut Context, &Function, &mut Frame, &RegisterOp) -> Result<(), VmError>;
+
+struct RegisterOp {
+    handler: RegisterHandler,
+
+    /// The instruction that the operation was translated from.
+    inst_index: usize,
+
+    dst: usize,
+    a: usize,
+    b: usize,
+
+    /// The bits of a constant, or the operand stack depth before an
+    /// instruction that executes on the operand stack.
+    operand: u64,
+}
+
+/// Handlers execute a single instruction on the topmost frame.
+type Handler = unsafe fn(&mut Context, &Function, &mut Frame, u64) -> Result<(), VmError>;
+
+#[derive(Clone, Copy)]
+struct ThreadedOp {
+    handler: Handler,
+
+    /// A local index, the bits of a constant, a type, or the index of an
+    /// instruction that the handler executes through execute_instruction.
+    operand: u64,
+}
+
 pub struct Context {
     /// The general stack is 8-byte aligned.
     stack: *mut u8,
 
     /// The amount of elements on the stack.
     stack_length: usize,
+
+    /// The stack grows on demand up to this amount of elements.
+    max_stack_length: usize,
+
+    /// The amount of bytes the stack, the heap, runtime strings and compiled
+    /// code may occupy together, if limited.
+    memory_limit: Option<usize>,
+
+    /// The size of the largest runtime string since the last run started.
+    string_peak: usize,
+
+    /// Used by type tests and casts.
+    types: Arc<TypeHierarchy>,
+
+    /// Cleared at the start of every run.
+    heap: Heap,
+
+    /// One frame per active call, innermost last. Also holds the frame of a
+    /// suspended execution until it is resumed or a new run starts.
+    frames: Vec<Frame>,
+
+    /// The remaining fuel, or None if execution is not metered.
+    fuel: Option<u64>,
+
+    /// The fuel consumed since it was last set.
+    fuel_consumed: u64,
+
+    fuel_costs: FuelCosts,
+
+    interrupt: InterruptHandle,
+
+    /// Execution uses a slower loop that checks breakpoints and watchpoints
+    /// only while a debugger is attached.
+    debugger: Option<Debugger>,
+
+    /// Like the debugger, an observer makes execution use the slower loop.
+    observer: Option<Box<ExecutionObserver>>,
+
+    /// Like the debugger, profiling makes execution use the slower loop.
+    profiler: Option<Profiler>,
+
+    engine: Engine,
+
+    /// Bounds-checks the operand stack and local accesses of every
+    /// instruction, which makes execution use the slower loop.
+    checked: bool,
+
+    /// Canonicalizes float results and defines float-to-int conversions,
+    /// which makes execution use the switch loop.
+    deterministic_floats: bool,
+
+    /// Compiles hot functions, which then bypass the engine.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    jit: Option<Jit>,
+
+    /// Set when execution is suspended at a breakpoint, so that the
+    /// instruction at the breakpoint executes on resumption.
+    skip_breakpoint: bool,
+}
+
+/// The registers of an active call. The frame of the innermost call is only
+/// updated in the Context when execution stops.
+#[derive(Clone, Copy)]
+struct Frame {
+    /// The function outlives the call. When execution is suspended, the
+    /// function is attached again on resumption, since it might have moved.
+    function: *const Function,
+
+    /// Can be used without dereferencing the function pointer.
+    function_id: u32,
+
+    inst_index: usize,
+
+    /// Where the locals of the function start.
+    stack_bottom: usize,
+
+    /// Where the operand stack starts.
+    operands_bottom: usize,
+
+    /// Operand stack top is exclusive.
+    op_stack_top: usize,
+
+    stack_return: usize,
 }
 
 
@@ -28,10 +206,38 @@ macro_rules! dsa {
     };
 }
 
-/// Typed stack access.
-macro_rules! tsa {
-    ( $type_enum:expr, $ptr:expr, $pos:expr ) => {
-        *$ptr.offset(sao($type_enum, $pos))
+/// Converts a float to the integer type with defined results: NaN becomes
+/// zero, and values out of range saturate at the bounds of the type.
+/// Evaluates to the slot of the integer.
+macro_rules! saturate {
+    ( $value:expr, $t:ty ) => {
+        {
+            let value: f64 = $value;
+            let result: $t = if value.is_nan() {
+                0
+            } else if value <= <$t>::min_value() as f64 {
+                <$t>::min_value()
+            } else if value >= <$t>::max_value() as f64 {
+                <$t>::max_value()
+            } else {
+                value as $t
+            };
+            result.to_slot()
+        }
+    };
+}
+
+/// Typed stack read. Expects a u64 stack pointer.
+macro_rules! tsr {
+    ( $t:ty, $ptr:expr, $pos:expr ) => {
+        <$t as SlotValue>::from_slot(*$ptr.offset($pos as isize))
+    };
+}
+
+/// Typed stack write. Expects a u64 stack pointer.
+macro_rules! tsw {
+    ( $ptr:expr, $pos:expr, $value:expr ) => {
+        *$ptr.offset($pos as isize) = SlotValue::to_slot($value)
     };
 }
 
@@ -47,38 +253,437 @@ macro_rules! workaround_expr {
     }
 }
 
+/// Resolves a big integer handle or returns an error from the enclosing function.
+macro_rules! heap_big_int {
+    ( $heap:expr, $handle:expr ) => {
+        {
+            let handle = $handle;
+            match $heap.big_int(handle) {
+                Some(value) => value,
+                None => return Err(VmError::InvalidHandle(handle)),
+            }
+        }
+    };
+}
+
 macro_rules! stack_op {
-    ( $type_enum:expr, $ptr:expr, $top:ident, $op:tt ) => {
+    ( $t:ty, $ptr:expr, $top:ident, $op:tt ) => {
         {
             let left = $top - 2;
             let right = $top - 1;
-            tsa!($type_enum, $ptr, left) = workaround_expr!(tsa!($type_enum, $ptr, left) $op tsa!($type_enum, $ptr, right));
+            let result: $t = workaround_expr!(tsr!($t, $ptr, left) $op tsr!($t, $ptr, right));
+            tsw!($ptr, left, result);
             $top = right;
         }
     };
 }
 
-/// Expects t as a Type enum reference.
+/// Integer operations wrap around on overflow. If check_zero is true, a
+/// right operand of zero is reported as a division by zero.
+macro_rules! int_stack_op {
+    ( $t:ty, $ptr:expr, $top:ident, $method:ident, $check_zero:expr ) => {
+        {
+            let left = $top - 2;
+            let right = $top - 1;
+            let b = tsr!($t, $ptr, right);
+            if $check_zero && b == 0 {
+                return Err(VmError::DivisionByZero);
+            }
+            tsw!($ptr, left, tsr!($t, $ptr, left).$method(b));
+            $top = right;
+        }
+    };
+}
+
+/// The operands are handles to big integers on the heap. The result
+/// is allocated on the heap.
+macro_rules! big_int_op {
+    ( $heap:expr, $ptr:expr, $top:ident, $op:tt, $check_zero:expr ) => {
+        {
+            let left = $top - 2;
+            let right = $top - 1;
+            let result = {
+                let a = heap_big_int!($heap, dsa!($ptr, left));
+                let b = heap_big_int!($heap, dsa!($ptr, right));
+                if $check_zero && b.is_zero() {
+                    return Err(VmError::DivisionByZero);
+                }
+                workaround_expr!(a $op b)
+            };
+            dsa!($ptr, left) = try!($heap.allocate_big_int(result));
+            $top = right;
+        }
+    };
+}
+
+/// Expects t as a Type enum reference. The operator is used for floats and
+/// big integers, the method for all other integers.
 macro_rules! match_op {
-    ( $stack:ident, $t:ident, $top:ident, $op:tt ) => {
+    ( $heap:expr, $stack:ident, $t:ident, $top:ident, $op:tt, $method:ident, $check_zero:expr ) => {
+        {
+            match *($t) {
+                Type::U64 => int_stack_op!(u64, $stack, $top, $method, $check_zero),
+                Type::U32 => int_stack_op!(u32, $stack, $top, $method, $check_zero),
+                Type::I64 => int_stack_op!(i64, $stack, $top, $method, $check_zero),
+                Type::I32 => int_stack_op!(i32, $stack, $top, $method, $check_zero),
+                Type::F64 => stack_op!(f64, $stack, $top, $op),
+                Type::F32 => stack_op!(f32, $stack, $top, $op),
+                Type::BigInt => big_int_op!($heap, $stack, $top, $op, $check_zero),
+                _ => return Err(VmError::UnsupportedType(*$t)),
+            }
+        }
+    }
+}
+
+macro_rules! stack_cmp {
+    ( $t:ty, $ptr:expr, $top:ident ) => {
+        {
+            let left = $top - 2;
+            let right = $top - 1;
+            let ordering = tsr!($t, $ptr, left).partial_cmp(&tsr!($t, $ptr, right));
+            tsw!($ptr, left, ordering_value(ordering));
+            $top = right;
+        }
+    };
+}
+
+/// Expects t as a Type enum reference.
+macro_rules! match_cmp {
+    ( $heap:expr, $stack:ident, $t:ident, $top:ident ) => {
         {
             match *($t) {
-                Type::U64 => stack_op!(Type::U64, $stack as *mut u64, $top, $op),
-                Type::U32 => stack_op!(Type::U32, $stack as *mut u32, $top, $op),
-                Type::I64 => stack_op!(Type::I64, $stack as *mut i64, $top, $op),
-                Type::I32 => stack_op!(Type::I32, $stack as *mut i32, $top, $op),
-                Type::F64 => stack_op!(Type::F64, $stack as *mut f64, $top, $op),
-                Type::F32 => stack_op!(Type::F32, $stack as *mut f32, $top, $op),
-                _ => panic!("Unsupported type!"),
+                Type::U64 => stack_cmp!(u64, $stack, $top),
+                Type::U32 => stack_cmp!(u32, $stack, $top),
+                Type::I64 => stack_cmp!(i64, $stack, $top),
+                Type::I32 => stack_cmp!(i32, $stack, $top),
+                Type::F64 => stack_cmp!(f64, $stack, $top),
+                Type::F32 => stack_cmp!(f32, $stack, $top),
+                Type::BigInt => {
+                    let left = $top - 2;
+                    let right = $top - 1;
+                    let ordering = {
+                        let a = heap_big_int!($heap, dsa!($stack, left));
+                        let b = heap_big_int!($heap, dsa!($stack, right));
+                        a.cmp(b)
+                    };
+                    tsw!($stack, left, ordering_value(Some(ordering)));
+                    $top = right;
+                },
+                _ => return Err(VmError::UnsupportedType(*$t)),
             }
         }
     }
 }
 
+/// Reads the value at the stack index as the primitive type $from and
+/// writes it back as the type to. Conversions between primitive types
+/// follow the semantics of 'as'. Conversions to big integers truncate
+/// floats toward zero and fail for infinite and NaN values.
+macro_rules! conv_primitive {
+    ( $heap:expr, $ptr:expr, $index:expr, $from:ty, $to_big:ident, $to:ident ) => {
+        {
+            let value = tsr!($from, $ptr, $index);
+            match *($to) {
+                Type::U64 => tsw!($ptr, $index, value as u64),
+                Type::U32 => tsw!($ptr, $index, value as u32),
+                Type::I64 => tsw!($ptr, $index, value as i64),
+                Type::I32 => tsw!($ptr, $index, value as i32),
+                Type::U16 => tsw!($ptr, $index, value as u16),
+                Type::U8 => tsw!($ptr, $index, value as u8),
+                Type::I16 => tsw!($ptr, $index, value as i16),
+                Type::I8 => tsw!($ptr, $index, value as i8),
+                Type::F64 => tsw!($ptr, $index, value as f64),
+                Type::F32 => tsw!($ptr, $index, value as f32),
+                Type::BigInt => match BigInt::$to_big(value) {
+                    Some(big) => dsa!($ptr, $index) = try!($heap.allocate_big_int(big)),
+                    None => return Err(VmError::ConversionOverflow { value: value.to_string(), to: Type::BigInt }),
+                },
+                _ => return Err(VmError::UnsupportedType(*$to)),
+            }
+        }
+    };
+}
+
+/// Converts the big integer to the primitive type $to, which must be able
+/// to represent the value.
+macro_rules! conv_big_int {
+    ( $ptr:expr, $index:expr, $value:expr, $to_enum:expr, $to:ty, $method:ident ) => {
+        match $value.$method() {
+            Some(result) => tsw!($ptr, $index, result as $to),
+            None => return Err(VmError::ConversionOverflow { value: $value.to_string(), to: $to_enum }),
+        }
+    };
+}
+
+/// Generates accessors for operands and locals of the innermost frame,
+/// which read the stack element as the given type.
+macro_rules! inspect {
+    ( $operand:ident, $local:ident, $t:ty ) => {
+        pub fn $operand(&self, index: usize) -> Option<$t> {
+            self.operand_position(index).map(|position| unsafe {
+                tsr!($t, self.stack as *mut u64, position)
+            })
+        }
+
+        pub fn $local(&self, index: usize) -> Option<$t> {
+            self.local_position(index).map(|position| unsafe {
+                tsr!($t, self.stack as *mut u64, position)
+            })
+        }
+    };
+}
+
 impl Context {
+    /// Creates a context with a stack of fixed length.
     pub fn new(stack_length: usize) -> Context {
+        Context::with_stack_limits(stack_length, stack_length)
+    }
+
+    /// Creates a context with a stack that starts with initial_length elements
+    /// and grows when a call needs more, up to max_length elements.
+    pub fn with_stack_limits(initial_length: usize, max_length: usize) -> Context {
+        let stack_length = cmp::max(cmp::min(initial_length, max_length), 1);
         let stack = unsafe { allocate(stack_length * STACK_ELEMENT_SIZE, STACK_ALIGN) };
-        Context { stack: stack, stack_length: stack_length }
+        Context {
+            stack: stack,
+            stack_length: stack_length,
+            max_stack_length: max_length,
+            memory_limit: None,
+            string_peak: 0,
+            types: Arc::new(TypeHierarchy::new()),
+            heap: Heap::new(),
+            frames: Vec::new(),
+            fuel: None,
+            fuel_consumed: 0,
+            fuel_costs: FuelCosts::uniform(),
+            interrupt: InterruptHandle::new(),
+            debugger: None,
+            observer: None,
+            profiler: None,
+            engine: Engine::Threaded,
+            checked: false,
+            deterministic_floats: false,
+            #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+            jit: None,
+            skip_breakpoint: false,
+        }
+    }
+
+    /// Replaces the attached debugger, if any.
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take()
+    }
+
+    /// Replaces the observer, if any. Observers that collect data can share
+    /// it with the host, for example through an Rc<RefCell<..>>.
+    pub fn set_observer(&mut self, observer: Box<ExecutionObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn remove_observer(&mut self) -> Option<Box<ExecutionObserver>> {
+        self.observer.take()
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    /// Checks each instruction against the frame before executing it, so
+    /// that malformed bytecode fails with an error instead of accessing
+    /// memory outside of the frame.
+    pub fn set_checked(&mut self, checked: bool) {
+        self.checked = checked;
+    }
+
+    pub fn is_checked(&self) -> bool {
+        self.checked
+    }
+
+    /// Makes float results bit-identical across machines. Every F32 or F64
+    /// result, constant or argument that is NaN is replaced by the canonical
+    /// quiet NaN, and float to integer conversions of NaN give zero, while
+    /// values out of range saturate at the bounds of the integer type. The
+    /// instruction set has no float intrinsics whose results depend on the
+    /// platform, so none have to be rejected.
+    ///
+    /// Raw arguments are only known to be floats if the function has a
+    /// signature. Without one, a NaN argument keeps its payload until a float
+    /// operation consumes it, so a function that only loads, stores or
+    /// returns it passes the host's bits back unchanged. That is still the
+    /// same on every machine, since no float operation has touched them.
+    pub fn set_deterministic_floats(&mut self, deterministic: bool) {
+        self.deterministic_floats = deterministic;
+    }
+
+    pub fn has_deterministic_floats(&self) -> bool {
+        self.deterministic_floats
+    }
+
+    /// Compiles functions into machine code after the given number of calls.
+    /// Compiled functions are only used when execution is neither metered,
+    /// stepped, instrumented nor float-deterministic. Only functions fetched
+    /// from an environment are compiled. Compiled code counts against the
+    /// memory limit, and functions whose code does not fit are interpreted.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    pub fn enable_jit(&mut self, threshold: u32) {
+        self.jit = Some(Jit::new(threshold));
+        self.update_heap_limit();
+    }
+
+    /// Discards all compiled code.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    pub fn disable_jit(&mut self) {
+        self.jit = None;
+        self.update_heap_limit();
+    }
+
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    pub fn is_jit_compiled(&self, function: &Function) -> bool {
+        match self.jit {
+            Some(ref jit) => jit.is_compiled(function),
+            None => false,
+        }
+    }
+
+    /// Starts collecting a new profile. Counting makes execution use the
+    /// slower loop, and timing adds some work to every call and resumption.
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// Stops profiling and returns the collected profile.
+    pub fn disable_profiling(&mut self) -> Option<ProfileReport> {
+        self.profiler.take().map(|profiler| profiler.report())
+    }
+
+    /// The profile collected so far, if profiling is enabled.
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profiler.as_ref().map(|profiler| profiler.report())
+    }
+
+    /// Lets the host change breakpoints and watchpoints, for example while
+    /// execution is suspended.
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// Returns a handle that other threads can use to interrupt execution.
+    /// An interrupted execution is suspended with VmError::Interrupted. An
+    /// interrupt applies to the execution that is running or suspended when
+    /// it is requested. Starting a new run discards a pending interrupt.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Limits execution to the given amount of fuel. When an instruction needs
+    /// more fuel than remains, execution is suspended with VmError::OutOfFuel.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+        self.fuel_consumed = 0;
+    }
+
+    /// Tops up the remaining fuel, for example before resuming.
+    /// Does nothing if execution is not metered.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = self.fuel {
+            self.fuel = Some(remaining.saturating_add(fuel));
+        }
+    }
+
+    /// Stops metering execution.
+    pub fn disable_fuel(&mut self) {
+        self.fuel = None;
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn fuel_consumed(&self) -> u64 {
+        self.fuel_consumed
+    }
+
+    /// Replaces the default cost of one fuel unit per instruction.
+    pub fn set_fuel_costs(&mut self, fuel_costs: FuelCosts) {
+        self.fuel_costs = fuel_costs;
+    }
+
+    /// Sets the type hierarchy that 'isinstance' and 'cast' are checked against.
+    /// Usually handed over from Environment::type_hierarchy.
+    pub fn set_type_hierarchy(&mut self, types: Arc<TypeHierarchy>) {
+        self.types = types;
+    }
+
+    /// Resolves a big integer handle, for example one returned by run.
+    /// Handles are valid until the next run.
+    pub fn big_int(&self, handle: u64) -> Option<&BigInt> {
+        self.heap.big_int(handle)
+    }
+
+    /// The amount of elements the stack currently has.
+    pub fn stack_length(&self) -> usize {
+        self.stack_length
+    }
+
+    pub fn max_stack_length(&self) -> usize {
+        self.max_stack_length
+    }
+
+    /// Changes how far the stack may grow. Does not shrink the stack.
+    pub fn set_max_stack_length(&mut self, max_length: usize) {
+        self.max_stack_length = max_length;
+    }
+
+    /// Limits the memory of the stack, the heap, runtime strings and compiled
+    /// code to the given amount of bytes. Allocations that would exceed the budget fail
+    /// with VmError::OutOfMemory. The current stack is never shrunk.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = Some(bytes);
+        self.update_heap_limit();
+    }
+
+    pub fn remove_memory_limit(&mut self) {
+        self.memory_limit = None;
+        self.update_heap_limit();
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            stack: self.stack_size(),
+            heap: self.heap.bytes(),
+            strings: self.string_peak,
+            code: self.code_size(),
+        }
+    }
+
+    /// The heap may use what the stack and compiled code leave of the budget.
+    fn update_heap_limit(&mut self) {
+        let reserved = self.stack_size() + self.code_size();
+        self.heap.set_limit(self.memory_limit.map(|limit| limit.saturating_sub(reserved)));
+    }
+
+    /// The amount of bytes a runtime string may occupy.
+    fn string_budget(&self) -> Option<usize> {
+        self.memory_limit.map(|limit| limit.saturating_sub(self.memory_usage().total()))
+    }
+
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    fn code_size(&self) -> usize {
+        self.jit.as_ref().map_or(0, |jit| jit.code_size())
+    }
+
+    #[cfg(not(all(feature = "jit", target_arch = "x86_64", target_os = "linux")))]
+    #[inline(always)]
+    fn code_size(&self) -> usize {
+        0
     }
 
     fn u64_stack_view(&self) -> *mut u64 {
@@ -90,20 +695,85 @@ impl Context {
         return self.stack_length * STACK_ELEMENT_SIZE;
     }
 
-    /// Returns a vector of function results.
-    pub fn run(&self, function: &Function, arguments: &Vec<u64>) -> Vec<u64> {
+    /// Runs the function with arguments that are checked against its
+    /// signature, such as a tuple of numbers. Returns the typed results, or
+    /// the error that stopped execution.
+    pub fn run<A>(&mut self, function: &Function, arguments: A) -> Result<Vec<Value>, Fault>
+            where A: IntoValues {
+        let arguments = arguments.into_values();
+        try!(check_arguments(function, &arguments));
+
+        // Big integers are allocated after start has cleared the heap.
+        try!(self.start(function, &vec![0; arguments.len()]));
+        let return_count = function.sizes.return_count as isize;
+        for (i, argument) in arguments.into_iter().enumerate() {
+            let slot = match value_slot(&mut self.heap, argument) {
+                Ok(slot) => slot,
+                Err(error) => {
+                    let fault = Fault::new(error, self.stack_trace());
+                    self.pop_frame(function, Some(&fault));
+                    return Err(fault);
+                },
+            };
+            unsafe {
+                *self.u64_stack_view().offset(return_count + i as isize) = slot;
+            }
+        }
+        self.canonicalize_arguments(function);
+
+        try!(self.finish_call(function));
+        let results = self.results(function, 0);
+        self.values(function, &results).map_err(Fault::from)
+    }
+
+    /// Runs the function with the raw bits of its argument slots. Returns a
+    /// vector of raw function results, or the error that stopped execution.
+    pub fn run_raw(&mut self, function: &Function, arguments: &Vec<u64>) -> Result<Vec<u64>, Fault> {
+        try!(self.start(function, arguments));
+        try!(self.finish_call(function));
+        Ok(self.results(function, 0))
+    }
+
+    /// Converts raw results, for example ones returned by resume, into typed
+    /// values according to the signature of the function.
+    pub fn values(&self, function: &Function, results: &[u64]) -> Result<Vec<Value>, VmError> {
+        let signature = match function.signature {
+            Some(ref signature) if signature.returns.len() == results.len() => signature,
+            _ => return Err(VmError::InvalidSignature { function: function.name.clone() }),
+        };
+        signature.returns.iter().zip(results).map(|(t, slot)| slot_value(&self.heap, *t, *slot)).collect()
+    }
+
+    /// Prepares the execution of the function without executing any of its
+    /// instructions, so that it can be stepped through or resumed.
+    pub fn start(&mut self, function: &Function, arguments: &Vec<u64>) -> Result<(), Fault> {
         if function.id == INVALID_FUNCTION_ID {
-            panic!("The function must be registered with an environment.");
+            return Err(Fault::from(VmError::UnregisteredFunction { function: function.name.clone() }));
         }
 
         let argument_count = function.sizes.argument_count as usize;
 
         if arguments.len() != argument_count {
-            panic!("Expected {} arguments but got {}.", arguments.len(), argument_count);
+            return Err(Fault::from(VmError::ArgumentCountMismatch {
+                function: function.name.clone(),
+                expected: argument_count,
+                actual: arguments.len(),
+            }));
         }
 
         let return_count = function.sizes.return_count as usize;
 
+        // The arguments are written before the call checks the stack size.
+        try!(self.check_stack_size(function, return_count));
+
+        // Discards a suspended execution, and an interrupt meant for it or
+        // for a run that has already finished.
+        self.interrupt.clear();
+        self.frames.clear();
+        self.skip_breakpoint = false;
+        self.heap.clear();
+        self.string_peak = 0;
+
         // Push arguments to the locals part of the stack.
         // Locals start at offset return_count.
         for i in 0..argument_count {
@@ -112,145 +782,1647 @@ impl Context {
             }
         }
 
+        self.canonicalize_arguments(function);
+
         // The return stack is filled at 0..return_count.
-        self.call(function, return_count, 0);
-        let mut result = vec![];
-        for i in 0..return_count {
+        self.push_frame(function, return_count, 0);
+        Ok(())
+    }
+
+    /// Replaces NaN arguments with the canonical quiet NaN if floats are
+    /// deterministic. The float arguments are known from the signature, so
+    /// raw arguments of functions without one are left as they are, see
+    /// set_deterministic_floats.
+    fn canonicalize_arguments(&mut self, function: &Function) {
+        if !self.deterministic_floats {
+            return;
+        }
+        let signature = match function.signature {
+            Some(ref signature) if signature.fits(&function.sizes) => signature,
+            _ => return,
+        };
+        let return_count = function.sizes.return_count as isize;
+        for (i, t) in signature.arguments.iter().enumerate() {
             unsafe {
-                result.push(*(self.u64_stack_view().offset(i as isize)));
+                let slot = self.u64_stack_view().offset(return_count + i as isize);
+                *slot = canonical_float_slot(*t, *slot);
             }
         }
-        result
     }
 
-    pub fn call(&self, function: &Function, stack_bottom: usize,
-                   stack_return: usize) {
-        let insts;
-        match function.instructions {
-            Instructions::Bytecode(ref vec) => insts = vec,
-            Instructions::File {..} => {
-                panic!("Bytecode expected, but got file path for function '{}'!",
-                    function.name);
-            }
-        }
-        let inst_count = insts.len();
-        let mut inst_index = 0;
+    /// Continues an execution that was suspended, for example because it ran
+    /// out of fuel. The function must be the one that was suspended. Only
+    /// the execution of a single active call can be resumed.
+    pub fn resume(&mut self, function: &Function) -> Result<Vec<u64>, Fault> {
+        let stack_return = try!(self.attach_suspended(function));
+        try!(self.finish_call(function));
+        Ok(self.results(function, stack_return))
+    }
 
-        // Operand stack top is exclusive.
-        // The operand stack comes after the locals.
-        let mut op_stack_top: usize = stack_bottom + function.sizes.locals_count as usize;
+    /// Executes the next instruction of a suspended execution, for example
+    /// one prepared with start. Fuel and interrupts apply as usual.
+    pub fn step(&mut self, function: &Function) -> Result<Step, Fault> {
+        let stack_return = try!(self.attach_suspended(function));
+        let result = self.execute(function, true);
+        match result {
+            Err(ref fault) if fault.error.is_suspension() => { },
+            Err(ref fault) => self.pop_frame(function, Some(fault)),
+            Ok(()) => {
+                if self.frames.last().unwrap().inst_index >= function.instruction_count() {
+                    self.pop_frame(function, None);
+                    return Ok(Step::Finished(self.results(function, stack_return)));
+                }
+            },
+        }
+        result.map(|_| Step::Paused)
+    }
 
-        // Checks and prevents stack overflows.
-        if op_stack_top + function.sizes.max_operands as usize >= self.stack_size() {
-            panic!("Stack overflow occured!");
+    /// Attaches the function to the suspended frame. Returns where the results go.
+    fn attach_suspended(&mut self, function: &Function) -> Result<usize, Fault> {
+        if self.frames.len() != 1 {
+            return Err(Fault::from(VmError::NotSuspended));
         }
 
-        // Locals view.
-        let locals: *mut u64 = unsafe {
-            (self.stack as *mut u64).offset(function.sizes.return_count as isize)
-        };
+        let frame = self.frames.last_mut().unwrap();
+        if frame.function_id != function.id {
+            return Err(Fault::from(VmError::ResumeMismatch {
+                expected: frame.function_id,
+                actual: function.id,
+            }));
+        }
+        frame.function = function as *const Function;
+        Ok(frame.stack_return)
+    }
 
-        // Stack views.
-        let sv_u64: *mut u64 = self.stack as *mut u64;
-        let sv_u32: *mut u32 = sv_u64 as *mut u32;
-        let sv_i64: *mut i64 = sv_u64 as *mut i64;
-        let sv_i32: *mut i32 = sv_u64 as *mut i32;
-        let sv_f64: *mut f64 = sv_u64 as *mut f64;
-        let sv_f32: *mut f32 = sv_u64 as *mut f32;
+    /// Whether there is an execution that can be stepped through or resumed.
+    pub fn is_suspended(&self) -> bool {
+        self.frames.len() == 1
+    }
 
-        while inst_index < inst_count {
-            let inst = &insts[inst_index];
-            match *inst {
-                Instruction::Nop => {
+    /// Captures the suspended execution, so that it can be restored later,
+    /// possibly by another process. The functions are looked up in the
+    /// environment by their IDs.
+    pub fn snapshot(&self, environment: &Environment) -> Result<Snapshot, VmError> {
+        if !self.is_suspended() {
+            return Err(VmError::NotSuspended);
+        }
 
-                },
+        let mut frames = Vec::with_capacity(self.frames.len());
+        for frame in &self.frames {
+            let function = match environment.find_function_by_id(frame.function_id) {
+                Some(function) => function,
+                None => return Err(VmError::InvalidSnapshot(
+                    format!("Function #{} does not exist in the environment.", frame.function_id))),
+            };
+            frames.push(FrameSnapshot {
+                function_name: function.name.clone(),
+                fingerprint: function.bytecode_fingerprint().unwrap_or(0),
+                inst_index: frame.inst_index,
+                stack_bottom: frame.stack_bottom,
+                operands_bottom: frame.operands_bottom,
+                op_stack_top: frame.op_stack_top,
+                stack_return: frame.stack_return,
+            });
+        }
 
-                Instruction::Pop => {
-                    op_stack_top -= 1
-                },
+        let top = self.frames.last().unwrap().op_stack_top;
+        let stack = (0..top).map(|i| unsafe { *self.u64_stack_view().offset(i as isize) }).collect();
+        Ok(Snapshot {
+            stack: stack,
+            big_ints: self.heap.big_ints().to_vec(),
+            frames: frames,
+            skip_breakpoint: self.skip_breakpoint,
+        })
+    }
 
-                Instruction::Dup => unsafe {
-                    dsa!(sv_u64, op_stack_top) = dsa!(sv_u64, op_stack_top - 1);
-                    op_stack_top += 1;
-                },
+    /// Replaces the state of the context with the suspended execution of the
+    /// snapshot, which can then be resumed or stepped through. The functions
+    /// of the snapshot are looked up by name and must have been fetched from
+    /// the environment with the same bytecode.
+    pub fn restore(&mut self, environment: &Environment, snapshot: &Snapshot) -> Result<(), VmError> {
+        let mut frames = Vec::with_capacity(snapshot.frames.len());
+        for frame in &snapshot.frames {
+            let function = match environment.get_function_by_name(&frame.function_name) {
+                Some(function) => function,
+                None => return Err(VmError::InvalidSnapshot(
+                    format!("Function '{}' is not registered.", frame.function_name))),
+            };
+            match function.bytecode_fingerprint() {
+                Some(fingerprint) if fingerprint == frame.fingerprint => { },
+                Some(..) => return Err(VmError::SnapshotMismatch { function: function.name.clone() }),
+                None => return Err(VmError::BytecodeNotLoaded { function: function.name.clone() }),
+            }
 
-                Instruction::Cst(ref index) => unsafe {
-                    let constant = &function.constant_table.table[*index as usize];
-                    match *constant {
-                        Constant::U64(num) => { tsa!(Type::U64, sv_u64, op_stack_top) = num; },
-                        Constant::U32(num) => { tsa!(Type::U32, sv_u32, op_stack_top) = num; },
-                        Constant::I64(num) => { tsa!(Type::I64, sv_i64, op_stack_top) = num; },
-                        Constant::I32(num) => { tsa!(Type::I32, sv_i32, op_stack_top) = num; },
-                        Constant::F64(num) => { tsa!(Type::F64, sv_f64, op_stack_top) = num; },
-                        Constant::F32(num) => { tsa!(Type::F32, sv_f32, op_stack_top) = num; },
-                        _ => panic!(format!("Constant at index {} must be a number!", index)),
-                    }
-                    op_stack_top += 1;
-                },
+            // The positions come from untrusted bytes, so sums may overflow.
+            let sizes = &function.sizes;
+            let locals_end = frame.stack_bottom.checked_add(sizes.locals_count as usize);
+            let operands_end = frame.operands_bottom.checked_add(sizes.max_operands as usize);
+            let return_end = frame.stack_return.checked_add(sizes.return_count as usize);
+            let consistent = locals_end == Some(frame.operands_bottom)
+                && frame.op_stack_top >= frame.operands_bottom
+                && operands_end.map_or(false, |end| frame.op_stack_top <= end)
+                && return_end.map_or(false, |end| end <= frame.stack_bottom)
+                && frame.inst_index <= function.instruction_count();
+            if !consistent || frame.op_stack_top != snapshot.stack.len() {
+                return Err(VmError::InvalidSnapshot(
+                    format!("The frame of function '{}' is inconsistent.", function.name)));
+            }
 
-                Instruction::Load(ref var) => unsafe {
-                    dsa!(sv_u64, op_stack_top) = dsa!(locals, *var);
-                    op_stack_top += 1;
-                },
+            frames.push(Frame {
+                function: function as *const Function,
+                function_id: function.id,
+                inst_index: frame.inst_index,
+                stack_bottom: frame.stack_bottom,
+                operands_bottom: frame.operands_bottom,
+                op_stack_top: frame.op_stack_top,
+                stack_return: frame.stack_return,
+            });
+        }
+        if frames.len() != 1 {
+            return Err(VmError::InvalidSnapshot("Expected a single suspended frame.".to_string()));
+        }
 
-                Instruction::Store(ref var) => unsafe {
-                    dsa!(locals, *var) = dsa!(sv_u64, op_stack_top - 1);
-                    op_stack_top -= 1;
-                },
+        // Everything that can fail happens before the stack is overwritten,
+        // so a failed restore leaves a suspended execution resumable.
+        let function = unsafe { &*frames[0].function };
+        try!(self.check_stack_size(function, frames[0].stack_bottom));
+        try!(self.heap.restore(snapshot.big_ints.clone()));
 
-                Instruction::Add(ref t) => unsafe {
-                    match_op!(sv_u64, t, op_stack_top, +);
-                },
+        for (i, slot) in snapshot.stack.iter().enumerate() {
+            unsafe {
+                *self.u64_stack_view().offset(i as isize) = *slot;
+            }
+        }
+        self.frames = frames;
+        self.skip_breakpoint = snapshot.skip_breakpoint;
+        Ok(())
+    }
 
-                Instruction::Sub(ref t) => unsafe {
-                    match_op!(sv_u64, t, op_stack_top, -);
-                },
+    /// The ID of the function that executes in the innermost frame.
+    pub fn current_function_id(&self) -> Option<u32> {
+        self.frames.last().map(|frame| frame.function_id)
+    }
 
-                Instruction::Mul(ref t) => unsafe {
-                    match_op!(sv_u64, t, op_stack_top, *);
-                },
+    /// The index of the next instruction to execute in the innermost frame.
+    pub fn current_inst_index(&self) -> Option<usize> {
+        self.frames.last().map(|frame| frame.inst_index)
+    }
 
-                Instruction::Div(ref t) => unsafe {
-                    match_op!(sv_u64, t, op_stack_top, /);
-                },
+    /// The number of elements on the operand stack of the innermost frame.
+    pub fn operand_count(&self) -> usize {
+        match self.frames.last() {
+            Some(frame) => frame.op_stack_top - frame.operands_bottom,
+            None => 0,
+        }
+    }
+
+    /// The stack position above the operands of the innermost frame, from
+    /// which a nested call can use the stack.
+    pub fn operand_stack_top(&self) -> Option<usize> {
+        self.frames.last().map(|frame| frame.op_stack_top)
+    }
+
+    /// The number of locals of the innermost frame, including the arguments.
+    pub fn local_count(&self) -> usize {
+        match self.frames.last() {
+            Some(frame) => frame.operands_bottom - frame.stack_bottom,
+            None => 0,
+        }
+    }
+
+    fn operand_position(&self, index: usize) -> Option<usize> {
+        if index < self.operand_count() {
+            self.frames.last().map(|frame| frame.operands_bottom + index)
+        } else {
+            None
+        }
+    }
+
+    fn local_position(&self, index: usize) -> Option<usize> {
+        if index < self.local_count() {
+            self.frames.last().map(|frame| frame.stack_bottom + index)
+        } else {
+            None
+        }
+    }
+
+    inspect!(operand_u64, local_u64, u64);
+    inspect!(operand_u32, local_u32, u32);
+    inspect!(operand_u16, local_u16, u16);
+    inspect!(operand_u8, local_u8, u8);
+    inspect!(operand_i64, local_i64, i64);
+    inspect!(operand_i32, local_i32, i32);
+    inspect!(operand_i16, local_i16, i16);
+    inspect!(operand_i8, local_i8, i8);
+    inspect!(operand_f64, local_f64, f64);
+    inspect!(operand_f32, local_f32, f32);
+
+    /// Copies the results of the function from the stack.
+    fn results(&self, function: &Function, stack_return: usize) -> Vec<u64> {
+        let mut result = vec![];
+        for i in 0..function.sizes.return_count as usize {
+            unsafe {
+                result.push(*(self.u64_stack_view().offset((stack_return + i) as isize)));
+            }
+        }
+        result
+    }
+
+    /// Grows the stack if the locals and operands of the function do not fit.
+    /// Fails if they would not fit on a stack of maximum length.
+    fn check_stack_size(&mut self, function: &Function, stack_bottom: usize) -> Result<(), VmError> {
+        let required = stack_bottom.saturating_add(function.sizes.locals_count as usize
+            + function.sizes.max_operands as usize);
+        if required > self.stack_length {
+            if required > self.max_stack_length {
+                return Err(VmError::StackOverflow {
+                    function: function.name.clone(),
+                    required: required,
+                    available: cmp::max(self.stack_length, self.max_stack_length),
+                });
+            }
+            try!(self.grow_stack(required));
+        }
+        Ok(())
+    }
+
+    /// Reallocates the stack with at least the required length, but no more
+    /// than the memory budget allows. Frames refer to the stack by index,
+    /// and pointers into it are derived anew for every instruction, so
+    /// nothing has to be fixed up.
+    fn grow_stack(&mut self, required: usize) -> Result<(), VmError> {
+        let required_size = match required.checked_mul(STACK_ELEMENT_SIZE) {
+            Some(size) => size,
+            None => return Err(VmError::OutOfMemory { requested: usize::max_value(), available: 0 }),
+        };
+
+        let doubled = self.stack_length.saturating_mul(2);
+        let mut length = cmp::max(required, cmp::min(doubled, self.max_stack_length));
+        if length.checked_mul(STACK_ELEMENT_SIZE).is_none() {
+            length = required;
+        }
+        if let Some(limit) = self.memory_limit {
+            let available = limit.saturating_sub(self.heap.bytes() + self.code_size());
+            if required_size > available {
+                return Err(VmError::OutOfMemory {
+                    requested: required_size - self.stack_size(),
+                    available: available.saturating_sub(self.stack_size()),
+                });
+            }
+            length = cmp::min(length, available / STACK_ELEMENT_SIZE);
+        }
 
-                Instruction::Ret(ref count) => unsafe {
-                    let count: usize = *count as usize;
-                    let dst = sv_u64.offset(stack_return as isize);
-                    let src = sv_u64.offset(op_stack_top as isize - count as isize);
-                    ptr::copy(src, dst, count);
+        // The old stack stays valid if it cannot be reallocated.
+        let size = length * STACK_ELEMENT_SIZE;
+        let stack = unsafe { reallocate(self.stack, self.stack_size(), size, STACK_ALIGN) };
+        if stack.is_null() {
+            return Err(VmError::OutOfMemory { requested: size - self.stack_size(), available: 0 });
+        }
+        self.stack = stack;
+        self.stack_length = length;
+        self.update_heap_limit();
+        Ok(())
+    }
+
+    /// Calls the function with its locals starting at stack_bottom. The results
+    /// are copied to stack_return.
+    ///
+    /// Until the bytecode has call instructions, this is how calls nest: the
+    /// host calls a function while another execution is suspended, placing
+    /// the locals and results at operand_stack_top so that the suspended
+    /// frame stays intact. The profiler counts such a call as part of the
+    /// inclusive time of the suspended functions.
+    pub fn call(&mut self, function: &Function, stack_bottom: usize,
+                   stack_return: usize) -> Result<(), Fault> {
+        self.push_frame(function, stack_bottom, stack_return);
+        self.finish_call(function)
+    }
+
+    fn push_frame(&mut self, function: &Function, stack_bottom: usize, stack_return: usize) {
+        // The operand stack comes after the locals.
+        let operands_bottom = stack_bottom + function.sizes.locals_count as usize;
+        self.frames.push(Frame {
+            function: function as *const Function,
+            function_id: function.id,
+            inst_index: 0,
+            stack_bottom: stack_bottom,
+            operands_bottom: operands_bottom,
+            op_stack_top: operands_bottom,
+            stack_return: stack_return,
+        });
+
+        if let Some(ref mut observer) = self.observer {
+            observer.on_call(function);
+        }
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.count_call(function.id, &function.name);
+        }
+    }
+
+    /// Pops the frame of a call that has returned, or that has been stopped
+    /// by the fault.
+    fn pop_frame(&mut self, function: &Function, fault: Option<&Fault>) {
+        self.frames.pop();
+
+        if let Some(ref mut observer) = self.observer {
+            match fault {
+                Some(fault) => observer.on_trap(function, fault),
+                None => observer.on_return(function),
+            }
+        }
+    }
+
+    /// Executes the topmost frame and pops it, unless execution is suspended.
+    fn finish_call(&mut self, function: &Function) -> Result<(), Fault> {
+        let result = self.execute(function, false);
+        match result {
+            Err(ref fault) if fault.error.is_suspension() => { },
+            Err(ref fault) => self.pop_frame(function, Some(fault)),
+            Ok(()) => self.pop_frame(function, None),
+        }
+        result
+    }
+
+    /// Executes the function of the topmost frame, or only its next instruction.
+    fn execute(&mut self, function: &Function, single_step: bool) -> Result<(), Fault> {
+        let insts = match function.instructions {
+            Instructions::Bytecode(ref vec) => vec,
+            Instructions::File {..} => {
+                let error = VmError::BytecodeNotLoaded { function: function.name.clone() };
+                return Err(Fault::new(error, self.stack_trace()));
+            }
+        };
+
+        // Checks and prevents stack overflows.
+        let mut frame = *self.frames.last().unwrap();
+        if let Err(error) = self.check_stack_size(function, frame.stack_bottom) {
+            return Err(Fault::new(error, self.stack_trace()));
+        }
+
+        // Calls and resumptions are safe points.
+        if let Err(error) = self.check_interrupt() {
+            return Err(Fault::new(error, self.stack_trace()));
+        }
+
+        let started = match self.profiler {
+            Some(..) => Some(Instant::now()),
+            None => None,
+        };
+
+        let result = if single_step {
+            // Stepping executes the instruction at a breakpoint.
+            self.skip_breakpoint = false;
+            if frame.inst_index < insts.len() {
+                let inst = &insts[frame.inst_index];
+                if let Some(ref mut observer) = self.observer {
+                    observer.on_instruction(function, frame.inst_index, inst);
+                }
+                if let Some(ref mut profiler) = self.profiler {
+                    profiler.count_instruction(frame.function_id, frame.inst_index, inst.opcode());
+                }
+                self.execute_one(function, inst, &mut frame)
+            } else {
+                Ok(())
+            }
+        } else if self.is_instrumented() {
+            self.execute_instrumented(function, insts, &mut frame)
+        } else if self.fuel.is_some() {
+            self.execute_metered(function, insts, &mut frame)
+        } else if self.deterministic_floats {
+            // Neither native code nor the handlers canonicalize float results.
+            self.execute_unmetered(function, insts, &mut frame)
+        } else {
+            // Native code can leave the rest of the function to the engines.
+            self.execute_native(function, &mut frame);
+            match (self.engine, &function.threaded_code, &function.register_code) {
+                (Engine::Threaded, &Some(ref code), _) if code.ops.len() == insts.len() => {
+                    self.execute_threaded(function, code, &mut frame)
+                },
+                (Engine::Register, _, &Some(ref code)) if code.inst_count == insts.len() &&
+                                                          frame.inst_index == 0 => {
+                    self.execute_register(function, code, &mut frame)
                 },
+                _ => self.execute_unmetered(function, insts, &mut frame),
+            }
+        };
+
+        *self.frames.last_mut().unwrap() = frame;
+        if let Some(started) = started {
+            self.add_profile_time(function, started.elapsed());
+        }
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Fault::new(error, self.stack_trace())),
+        }
+    }
+
+    fn execute_unmetered(&mut self, function: &Function, insts: &Vec<Instruction>,
+                         frame: &mut Frame) -> Result<(), VmError> {
+        let inst_count = insts.len();
+        while frame.inst_index < inst_count {
+            try!(self.check_interrupt());
+            try!(self.execute_instruction(function, &insts[frame.inst_index], frame));
+            frame.inst_index += 1;
+        }
+        Ok(())
+    }
+
+    fn execute_threaded(&mut self, function: &Function, code: &ThreadedCode,
+                        frame: &mut Frame) -> Result<(), VmError> {
+        let ops = &code.ops[..];
+        while frame.inst_index < ops.len() {
+            try!(self.check_interrupt());
+            let op = ops[frame.inst_index];
+            try!(unsafe { (op.handler)(self, function, frame, op.operand) });
+            frame.inst_index += 1;
+        }
+        Ok(())
+    }
+
+    /// Counts the call and executes the compiled code of the function, if it
+    /// is hot enough. The frame is left at the instruction that has to be
+    /// interpreted next, which is the end if the code ran to completion.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+    fn execute_native(&mut self, function: &Function, frame: &mut Frame) {
+        if frame.inst_index != 0 {
+            return;
+        }
+
+        let stack = self.stack as *mut u64;
+        let budget = self.memory_limit.map(|limit| limit.saturating_sub(self.memory_usage().total()));
+        let code_size = self.code_size();
+        let exit = match self.jit {
+            // The stack size has been checked for the frame.
+            Some(ref mut jit) => jit.enter(function, budget).map(|code| unsafe {
+                code.call(stack.offset(frame.stack_bottom as isize),
+                          stack.offset(frame.stack_return as isize))
+            }),
+            None => None,
+        };
 
-                Instruction::Print(ref t) => unsafe {
-                    match *t {
-                        Type::U64 => println!("{}", tsa!(Type::U64, sv_u64, op_stack_top - 1)),
-                        Type::U32 => println!("{}", tsa!(Type::U32, sv_u32, op_stack_top - 1)),
-                        Type::I64 => println!("{}", tsa!(Type::I64, sv_i64, op_stack_top - 1)),
-                        Type::I32 => println!("{}", tsa!(Type::I32, sv_i32, op_stack_top - 1)),
-                        Type::F64 => println!("{}", tsa!(Type::F64, sv_f64, op_stack_top - 1)),
-                        Type::F32 => println!("{}", tsa!(Type::F32, sv_f32, op_stack_top - 1)),
-                        _ => panic!("Unsupported type!"),
+        // Code has been compiled or freed.
+        if self.code_size() != code_size {
+            self.update_heap_limit();
+        }
+        if let Some((inst_index, operand_count)) = exit {
+            frame.inst_index = inst_index;
+            frame.op_stack_top = frame.operands_bottom + operand_count;
+        }
+    }
+
+    #[cfg(not(all(feature = "jit", target_arch = "x86_64", target_os = "linux")))]
+    #[inline(always)]
+    fn execute_native(&mut self, _: &Function, _: &mut Frame) { }
+
+    /// Can only execute a call from its start.
+    fn execute_register(&mut self, function: &Function, code: &RegisterCode,
+                        frame: &mut Frame) -> Result<(), VmError> {
+        for op in &code.ops[..] {
+            frame.inst_index = op.inst_index;
+            try!(unsafe { (op.handler)(self, function, frame, op) });
+        }
+        frame.inst_index = code.inst_count;
+        Ok(())
+    }
+
+    /// Consumes fuel before each instruction. An instruction that cannot be
+    /// paid for is not executed, so that it is the first one on resumption.
+    fn execute_metered(&mut self, function: &Function, insts: &Vec<Instruction>,
+                       frame: &mut Frame) -> Result<(), VmError> {
+        let inst_count = insts.len();
+        while frame.inst_index < inst_count {
+            try!(self.check_interrupt());
+            try!(self.execute_one(function, &insts[frame.inst_index], frame));
+        }
+        Ok(())
+    }
+
+    /// Whether instructions have to be checked or reported, which the fast
+    /// loops do not do.
+    fn is_instrumented(&self) -> bool {
+        self.checked || self.debugger.is_some() || self.observer.is_some() ||
+            self.profiler.is_some()
+    }
+
+    /// Adds the time to the function and, inclusively, to all functions of
+    /// the frame chain.
+    fn add_profile_time(&mut self, function: &Function, time: Duration) {
+        let mut active_function_ids: Vec<u32> = Vec::with_capacity(self.frames.len());
+        for frame in &self.frames {
+            if !active_function_ids.contains(&frame.function_id) {
+                active_function_ids.push(frame.function_id);
+            }
+        }
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.add_time(function.id, &function.name, &active_function_ids, time);
+        }
+    }
+
+    /// Checks breakpoints before and watchpoints after each instruction, and
+    /// reports instructions to the observer and the profiler. Fuel is consumed as in
+    /// execute_metered.
+    fn execute_instrumented(&mut self, function: &Function, insts: &Vec<Instruction>,
+                        frame: &mut Frame) -> Result<(), VmError> {
+        let inst_count = insts.len();
+        while frame.inst_index < inst_count {
+            try!(self.check_interrupt());
+            let inst_index = frame.inst_index;
+            let at_breakpoint = match self.debugger {
+                Some(ref debugger) => debugger.has_breakpoint(frame.function_id, inst_index),
+                None => false,
+            };
+            if at_breakpoint && !self.skip_breakpoint {
+                self.skip_breakpoint = true;
+                return Err(VmError::Breakpoint {
+                    function_id: frame.function_id,
+                    inst_index: inst_index,
+                });
+            }
+
+            let inst = &insts[inst_index];
+            if let Some(ref mut observer) = self.observer {
+                observer.on_instruction(function, inst_index, inst);
+            }
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.count_instruction(frame.function_id, inst_index, inst.opcode());
+            }
+            try!(self.execute_one(function, inst, frame));
+            self.skip_breakpoint = false;
+
+            if let Instruction::Store(local) = *inst {
+                let watched = match self.debugger {
+                    Some(ref debugger) => debugger.is_watched(frame.function_id, local),
+                    None => false,
+                };
+                if watched {
+                    return Err(VmError::Watchpoint {
+                        function_id: frame.function_id,
+                        local: local,
+                        inst_index: inst_index,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the instruction and advances the frame, paying for it if
+    /// execution is metered.
+    fn execute_one(&mut self, function: &Function, inst: &Instruction,
+                   frame: &mut Frame) -> Result<(), VmError> {
+        if self.checked {
+            try!(check_access(function, inst, frame));
+        }
+
+        if let Some(remaining) = self.fuel {
+            let cost = self.fuel_costs.cost(inst.opcode());
+            if cost > remaining {
+                return Err(VmError::OutOfFuel { consumed: self.fuel_consumed });
+            }
+            self.fuel = Some(remaining - cost);
+            self.fuel_consumed += cost;
+        }
+
+        try!(self.execute_instruction(function, inst, frame));
+        frame.inst_index += 1;
+        Ok(())
+    }
+
+    /// Fails with VmError::Interrupted and withdraws the interrupt if one is
+    /// pending. Called at safe points only.
+    #[inline(always)]
+    fn check_interrupt(&self) -> Result<(), VmError> {
+        if self.interrupt.is_interrupted() {
+            self.interrupt.clear();
+            return Err(VmError::Interrupted);
+        }
+        Ok(())
+    }
+
+    /// The frame chain of all active calls, innermost first.
+    fn stack_trace(&self) -> StackTrace {
+        let frames = self.frames.iter().rev().map(|frame| {
+            let function = unsafe { &*frame.function };
+            TraceFrame {
+                function_name: function.name.clone(),
+                function_id: function.id,
+                inst_index: frame.inst_index,
+            }
+        }).collect();
+        StackTrace { frames: frames }
+    }
+
+    /// Executes a single instruction of the function in the given frame.
+    #[inline(always)]
+    fn execute_instruction(&mut self, function: &Function, inst: &Instruction,
+                                  frame: &mut Frame) -> Result<(), VmError> {
+        let inst_index = frame.inst_index;
+        let stack_return = frame.stack_return;
+
+        // Operand stack top is exclusive.
+        let mut op_stack_top = frame.op_stack_top;
+
+        // Locals view.
+        let locals: *mut u64 = unsafe {
+            (self.stack as *mut u64).offset(frame.stack_bottom as isize)
+        };
+
+        // Stack view. Every value occupies a whole slot.
+        let sv_u64: *mut u64 = self.stack as *mut u64;
+
+        match *inst {
+            Instruction::Nop => {
+
+            },
+
+            Instruction::Pop => {
+                op_stack_top -= 1
+            },
+
+            Instruction::Dup => unsafe {
+                dsa!(sv_u64, op_stack_top) = dsa!(sv_u64, op_stack_top - 1);
+                op_stack_top += 1;
+            },
+
+            Instruction::Cst(ref index) => unsafe {
+                let constant = try!(constant(function, *index));
+                if let Constant::BigInt(ref num) = *constant {
+                    dsa!(sv_u64, op_stack_top) = try!(self.heap.allocate_big_int(num.clone()));
+                } else {
+                    match constant_slot_bits(constant) {
+                        Some(bits) => dsa!(sv_u64, op_stack_top) = bits,
+                        None => return Err(VmError::InvalidConstant { index: *index, expected: "a number" }),
                     }
-                    op_stack_top -= 1;
+                }
+                op_stack_top += 1;
+            },
+
+            Instruction::CstBits(_, bits) => unsafe {
+                dsa!(sv_u64, op_stack_top) = bits;
+                op_stack_top += 1;
+            },
+
+            Instruction::Load(ref var) => unsafe {
+                dsa!(sv_u64, op_stack_top) = dsa!(locals, *var);
+                op_stack_top += 1;
+            },
+
+            Instruction::Store(ref var) => unsafe {
+                dsa!(locals, *var) = dsa!(sv_u64, op_stack_top - 1);
+                op_stack_top -= 1;
+            },
+
+            Instruction::Add(ref t) => unsafe {
+                match_op!(self.heap, sv_u64, t, op_stack_top, +, wrapping_add, false);
+            },
+
+            Instruction::Sub(ref t) => unsafe {
+                match_op!(self.heap, sv_u64, t, op_stack_top, -, wrapping_sub, false);
+            },
+
+            Instruction::Mul(ref t) => unsafe {
+                match_op!(self.heap, sv_u64, t, op_stack_top, *, wrapping_mul, false);
+            },
+
+            Instruction::Div(ref t) => unsafe {
+                match_op!(self.heap, sv_u64, t, op_stack_top, /, wrapping_div, true);
+            },
+
+            Instruction::Ret(ref count) => unsafe {
+                let count: usize = *count as usize;
+                let dst = sv_u64.offset(stack_return as isize);
+                let src = sv_u64.offset(op_stack_top as isize - count as isize);
+                ptr::copy(src, dst, count);
+            },
+
+            Instruction::Print(ref t) => unsafe {
+                match *t {
+                    Type::U64 => println!("{}", tsr!(u64, sv_u64, op_stack_top - 1)),
+                    Type::U32 => println!("{}", tsr!(u32, sv_u64, op_stack_top - 1)),
+                    Type::I64 => println!("{}", tsr!(i64, sv_u64, op_stack_top - 1)),
+                    Type::I32 => println!("{}", tsr!(i32, sv_u64, op_stack_top - 1)),
+                    Type::F64 => println!("{}", tsr!(f64, sv_u64, op_stack_top - 1)),
+                    Type::F32 => println!("{}", tsr!(f32, sv_u64, op_stack_top - 1)),
+                    Type::BigInt => println!("{}", heap_big_int!(self.heap, dsa!(sv_u64, op_stack_top - 1))),
+                    _ => return Err(VmError::UnsupportedType(*t)),
+                }
+                op_stack_top -= 1;
+            },
+
+            // The operand is a type ID that occupies the whole slot, see
+            // Instruction::IsInstance. The result of a type test is 1 or 0,
+            // also occupying the whole slot.
+            Instruction::IsInstance(ref id) => unsafe {
+                let subtype = slot_type_id(dsa!(sv_u64, op_stack_top - 1));
+                let result = self.types.is_subtype(subtype, *id);
+                dsa!(sv_u64, op_stack_top - 1) = result as u64;
+            },
+
+            Instruction::Cast(ref id) => unsafe {
+                let subtype = slot_type_id(dsa!(sv_u64, op_stack_top - 1));
+                if !self.types.is_subtype(subtype, *id) {
+                    return Err(VmError::CastFailed { from: subtype, to: *id });
+                }
+            },
+
+            Instruction::Rem(ref t) => unsafe {
+                match_op!(self.heap, sv_u64, t, op_stack_top, %, wrapping_rem, true);
+            },
+
+            Instruction::Cmp(ref t) => unsafe {
+                match_cmp!(self.heap, sv_u64, t, op_stack_top);
+            },
+
+            Instruction::Conv(ref from, ref to) => unsafe {
+                try!(self.convert(from, to, op_stack_top - 1));
+            },
+
+            Instruction::Assert(ref index) => unsafe {
+                let condition = tsr!(i32, sv_u64, op_stack_top - 1);
+                op_stack_top -= 1;
+                if condition == 0 {
+                    return Err(trap(function, *index, inst_index));
+                }
+            },
+
+            Instruction::Abort(ref index) => {
+                return Err(trap(function, *index, inst_index));
+            },
+
+            // The template is printed as is, so it has to contain its own line
+            // breaks. It is only parsed here if the function has not been fetched.
+            Instruction::PrintFmt(ref index, ref argc) => unsafe {
+                let parsed;
+                let template = match function.templates.get(index) {
+                    Some(template) => template,
+                    None => {
+                        parsed = match *try!(constant(function, *index)) {
+                            Constant::Str(ref string) => try!(parse_template(string, *argc)),
+                            _ => return Err(VmError::InvalidConstant { index: *index, expected: "a string" }),
+                        };
+                        &parsed
+                    },
+                };
+                let argc = *argc as usize;
+                let output = try!(self.render(template, op_stack_top - argc));
+                self.string_peak = cmp::max(self.string_peak, output.len());
+                print!("{}", output);
+                op_stack_top -= argc;
+            },
+
+            Instruction::AddU64 => unsafe { int_stack_op!(u64, sv_u64, op_stack_top, wrapping_add, false) },
+            Instruction::AddU32 => unsafe { int_stack_op!(u32, sv_u64, op_stack_top, wrapping_add, false) },
+            Instruction::AddI64 => unsafe { int_stack_op!(i64, sv_u64, op_stack_top, wrapping_add, false) },
+            Instruction::AddI32 => unsafe { int_stack_op!(i32, sv_u64, op_stack_top, wrapping_add, false) },
+            Instruction::AddF64 => unsafe { stack_op!(f64, sv_u64, op_stack_top, +) },
+            Instruction::AddF32 => unsafe { stack_op!(f32, sv_u64, op_stack_top, +) },
+            Instruction::SubU64 => unsafe { int_stack_op!(u64, sv_u64, op_stack_top, wrapping_sub, false) },
+            Instruction::SubU32 => unsafe { int_stack_op!(u32, sv_u64, op_stack_top, wrapping_sub, false) },
+            Instruction::SubI64 => unsafe { int_stack_op!(i64, sv_u64, op_stack_top, wrapping_sub, false) },
+            Instruction::SubI32 => unsafe { int_stack_op!(i32, sv_u64, op_stack_top, wrapping_sub, false) },
+            Instruction::SubF64 => unsafe { stack_op!(f64, sv_u64, op_stack_top, -) },
+            Instruction::SubF32 => unsafe { stack_op!(f32, sv_u64, op_stack_top, -) },
+            Instruction::MulU64 => unsafe { int_stack_op!(u64, sv_u64, op_stack_top, wrapping_mul, false) },
+            Instruction::MulU32 => unsafe { int_stack_op!(u32, sv_u64, op_stack_top, wrapping_mul, false) },
+            Instruction::MulI64 => unsafe { int_stack_op!(i64, sv_u64, op_stack_top, wrapping_mul, false) },
+            Instruction::MulI32 => unsafe { int_stack_op!(i32, sv_u64, op_stack_top, wrapping_mul, false) },
+            Instruction::MulF64 => unsafe { stack_op!(f64, sv_u64, op_stack_top, *) },
+            Instruction::MulF32 => unsafe { stack_op!(f32, sv_u64, op_stack_top, *) },
+            Instruction::DivU64 => unsafe { int_stack_op!(u64, sv_u64, op_stack_top, wrapping_div, true) },
+            Instruction::DivU32 => unsafe { int_stack_op!(u32, sv_u64, op_stack_top, wrapping_div, true) },
+            Instruction::DivI64 => unsafe { int_stack_op!(i64, sv_u64, op_stack_top, wrapping_div, true) },
+            Instruction::DivI32 => unsafe { int_stack_op!(i32, sv_u64, op_stack_top, wrapping_div, true) },
+            Instruction::DivF64 => unsafe { stack_op!(f64, sv_u64, op_stack_top, /) },
+            Instruction::DivF32 => unsafe { stack_op!(f32, sv_u64, op_stack_top, /) },
+            Instruction::RemU64 => unsafe { int_stack_op!(u64, sv_u64, op_stack_top, wrapping_rem, true) },
+            Instruction::RemU32 => unsafe { int_stack_op!(u32, sv_u64, op_stack_top, wrapping_rem, true) },
+            Instruction::RemI64 => unsafe { int_stack_op!(i64, sv_u64, op_stack_top, wrapping_rem, true) },
+            Instruction::RemI32 => unsafe { int_stack_op!(i32, sv_u64, op_stack_top, wrapping_rem, true) },
+            Instruction::RemF64 => unsafe { stack_op!(f64, sv_u64, op_stack_top, %) },
+            Instruction::RemF32 => unsafe { stack_op!(f32, sv_u64, op_stack_top, %) },
+        }
+
+        if self.deterministic_floats {
+            if let Some(t) = float_result(inst).or_else(|| float_constant(function, inst)) {
+                unsafe {
+                    dsa!(sv_u64, op_stack_top - 1) = canonical_float_slot(t, dsa!(sv_u64, op_stack_top - 1));
+                }
+            }
+        }
+
+        frame.op_stack_top = op_stack_top;
+        Ok(())
+    }
+}
+
+impl Context {
+    unsafe fn convert(&mut self, from: &Type, to: &Type, index: usize) -> Result<(), VmError> {
+        let sv_u64 = self.stack as *mut u64;
+        if self.deterministic_floats {
+            let value = match *from {
+                Type::F64 => Some(tsr!(f64, sv_u64, index)),
+                Type::F32 => Some(tsr!(f32, sv_u64, index) as f64),
+                _ => None,
+            };
+            let slot = match (value, *to) {
+                (Some(value), Type::U64) => Some(saturate!(value, u64)),
+                (Some(value), Type::U32) => Some(saturate!(value, u32)),
+                (Some(value), Type::I64) => Some(saturate!(value, i64)),
+                (Some(value), Type::I32) => Some(saturate!(value, i32)),
+                (Some(value), Type::U16) => Some(saturate!(value, u16)),
+                (Some(value), Type::U8) => Some(saturate!(value, u8)),
+                (Some(value), Type::I16) => Some(saturate!(value, i16)),
+                (Some(value), Type::I8) => Some(saturate!(value, i8)),
+                _ => None,
+            };
+            if let Some(slot) = slot {
+                dsa!(sv_u64, index) = slot;
+                return Ok(());
+            }
+        }
+
+        match *from {
+            Type::U64 => conv_primitive!(self.heap, sv_u64, index, u64, from_u64, to),
+            Type::U32 => conv_primitive!(self.heap, sv_u64, index, u32, from_u32, to),
+            Type::I64 => conv_primitive!(self.heap, sv_u64, index, i64, from_i64, to),
+            Type::I32 => conv_primitive!(self.heap, sv_u64, index, i32, from_i32, to),
+            Type::U16 => conv_primitive!(self.heap, sv_u64, index, u16, from_u16, to),
+            Type::U8 => conv_primitive!(self.heap, sv_u64, index, u8, from_u8, to),
+            Type::I16 => conv_primitive!(self.heap, sv_u64, index, i16, from_i16, to),
+            Type::I8 => conv_primitive!(self.heap, sv_u64, index, i8, from_i8, to),
+            Type::F64 => conv_primitive!(self.heap, sv_u64, index, f64, from_f64, to),
+            Type::F32 => conv_primitive!(self.heap, sv_u64, index, f32, from_f32, to),
+            Type::BigInt => {
+                let value = heap_big_int!(self.heap, dsa!(sv_u64, index));
+                match *to {
+                    Type::U64 => conv_big_int!(sv_u64, index, value, Type::U64, u64, to_u64),
+                    Type::U32 => conv_big_int!(sv_u64, index, value, Type::U32, u32, to_u32),
+                    Type::I64 => conv_big_int!(sv_u64, index, value, Type::I64, i64, to_i64),
+                    Type::I32 => conv_big_int!(sv_u64, index, value, Type::I32, i32, to_i32),
+                    Type::U16 => conv_big_int!(sv_u64, index, value, Type::U16, u16, to_u16),
+                    Type::U8 => conv_big_int!(sv_u64, index, value, Type::U8, u8, to_u8),
+                    Type::I16 => conv_big_int!(sv_u64, index, value, Type::I16, i16, to_i16),
+                    Type::I8 => conv_big_int!(sv_u64, index, value, Type::I8, i8, to_i8),
+                    Type::F64 => conv_big_int!(sv_u64, index, value, Type::F64, f64, to_f64),
+                    Type::F32 => conv_big_int!(sv_u64, index, value, Type::F32, f32, to_f32),
+                    Type::BigInt => { },
+                    _ => return Err(VmError::UnsupportedType(*to)),
+                }
+            },
+            _ => return Err(VmError::UnsupportedType(*from)),
+        }
+        Ok(())
+    }
+
+    /// Renders the template with the stack elements starting at first, one
+    /// for each placeholder. The first placeholder refers to the first element.
+    unsafe fn render(&self, template: &Template, first: usize) -> Result<String, VmError> {
+        // The output is checked against the budget before it grows, and
+        // placeholders before they are padded to their width or rendered
+        // with their precision.
+        let budget = self.string_budget().unwrap_or(usize::max_value());
+        let reserve = |output: &String, size: usize| {
+            if output.len().saturating_add(size) > budget {
+                Err(VmError::OutOfMemory {
+                    requested: size,
+                    available: budget.saturating_sub(output.len()),
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        let sv_u64 = self.stack as *mut u64;
+        let mut output = String::new();
+        let mut index = first;
+        for segment in &template.segments {
+            match *segment {
+                Segment::Literal(ref text) => {
+                    try!(reserve(&output, text.len()));
+                    output.push_str(&text[..]);
+                },
+                Segment::Placeholder(ref t, ref spec) => {
+                    try!(reserve(&output, cmp::max(spec.width, spec.precision.unwrap_or(0))));
+                    let text = match *t {
+                        Type::I8 => spec.render_integer(tsr!(i8, sv_u64, index)),
+                        Type::I16 => spec.render_integer(tsr!(i16, sv_u64, index)),
+                        Type::I32 => spec.render_integer(tsr!(i32, sv_u64, index)),
+                        Type::I64 => spec.render_integer(tsr!(i64, sv_u64, index)),
+                        Type::U8 => spec.render_integer(tsr!(u8, sv_u64, index)),
+                        Type::U16 => spec.render_integer(tsr!(u16, sv_u64, index)),
+                        Type::U32 => spec.render_integer(tsr!(u32, sv_u64, index)),
+                        Type::U64 => spec.render_integer(tsr!(u64, sv_u64, index)),
+                        Type::F32 => spec.render_float(tsr!(f32, sv_u64, index)),
+                        Type::F64 => spec.render_float(tsr!(f64, sv_u64, index)),
+                        Type::BigInt => spec.render_integer(heap_big_int!(self.heap, dsa!(sv_u64, index))),
+                        _ => return Err(VmError::UnsupportedType(*t)),
+                    };
+                    try!(reserve(&output, text.len()));
+                    output.push_str(&text[..]);
+                    index += 1;
                 },
             }
-            inst_index += 1;
         }
+
+        Ok(output)
+    }
+}
+
+impl ThreadedCode {
+    /// Decodes the bytecode of the function, which must be loaded. Arithmetic
+    /// that has not been specialized still dispatches on the type, and
+    /// instructions without a dedicated handler are executed through
+    /// execute_instruction.
+    pub fn translate(function: &Function) -> ThreadedCode {
+        let insts = match function.instructions {
+            Instructions::Bytecode(ref vec) => vec,
+            Instructions::File {..} => panic!("The bytecode of function '{}' is not loaded.", function.name),
+        };
+
+        let ops = insts.iter().enumerate().map(|(index, inst)| {
+            let (handler, operand): (Handler, u64) = match *inst {
+                Instruction::Nop => (h_nop, 0),
+                Instruction::Pop => (h_pop, 0),
+                Instruction::Dup => (h_dup, 0),
+                Instruction::Load(var) => (h_load, var as u64),
+                Instruction::Store(var) => (h_store, var as u64),
+                Instruction::Add(t) => (h_add, t as u64),
+                Instruction::Sub(t) => (h_sub, t as u64),
+                Instruction::Mul(t) => (h_mul, t as u64),
+                Instruction::Div(t) => (h_div, t as u64),
+                Instruction::Rem(t) => (h_rem, t as u64),
+                Instruction::AddU64 => (h_add_u64, 0),
+                Instruction::AddU32 => (h_add_u32, 0),
+                Instruction::AddI64 => (h_add_i64, 0),
+                Instruction::AddI32 => (h_add_i32, 0),
+                Instruction::AddF64 => (h_add_f64, 0),
+                Instruction::AddF32 => (h_add_f32, 0),
+                Instruction::SubU64 => (h_sub_u64, 0),
+                Instruction::SubU32 => (h_sub_u32, 0),
+                Instruction::SubI64 => (h_sub_i64, 0),
+                Instruction::SubI32 => (h_sub_i32, 0),
+                Instruction::SubF64 => (h_sub_f64, 0),
+                Instruction::SubF32 => (h_sub_f32, 0),
+                Instruction::MulU64 => (h_mul_u64, 0),
+                Instruction::MulU32 => (h_mul_u32, 0),
+                Instruction::MulI64 => (h_mul_i64, 0),
+                Instruction::MulI32 => (h_mul_i32, 0),
+                Instruction::MulF64 => (h_mul_f64, 0),
+                Instruction::MulF32 => (h_mul_f32, 0),
+                Instruction::DivU64 => (h_div_u64, 0),
+                Instruction::DivU32 => (h_div_u32, 0),
+                Instruction::DivI64 => (h_div_i64, 0),
+                Instruction::DivI32 => (h_div_i32, 0),
+                Instruction::DivF64 => (h_div_f64, 0),
+                Instruction::DivF32 => (h_div_f32, 0),
+                Instruction::RemU64 => (h_rem_u64, 0),
+                Instruction::RemU32 => (h_rem_u32, 0),
+                Instruction::RemI64 => (h_rem_i64, 0),
+                Instruction::RemI32 => (h_rem_i32, 0),
+                Instruction::RemF64 => (h_rem_f64, 0),
+                Instruction::RemF32 => (h_rem_f32, 0),
+                Instruction::CstBits(_, bits) => (h_cst_bits, bits),
+
+                // Constants that have not been resolved, such as big integers,
+                // are allocated or fail at runtime.
+                _ => (h_execute, index as u64),
+            };
+            ThreadedOp { handler: handler, operand: operand }
+        }).collect();
+
+        ThreadedCode { ops: ops }
+    }
+}
+
+unsafe fn h_nop(_: &mut Context, _: &Function, _: &mut Frame, _: u64) -> Result<(), VmError> {
+    Ok(())
+}
+
+unsafe fn h_pop(_: &mut Context, _: &Function, frame: &mut Frame, _: u64) -> Result<(), VmError> {
+    frame.op_stack_top -= 1;
+    Ok(())
+}
+
+unsafe fn h_dup(context: &mut Context, _: &Function, frame: &mut Frame, _: u64) -> Result<(), VmError> {
+    let sv_u64 = context.stack as *mut u64;
+    dsa!(sv_u64, frame.op_stack_top) = dsa!(sv_u64, frame.op_stack_top - 1);
+    frame.op_stack_top += 1;
+    Ok(())
+}
+
+unsafe fn h_load(context: &mut Context, _: &Function, frame: &mut Frame, var: u64) -> Result<(), VmError> {
+    let sv_u64 = context.stack as *mut u64;
+    dsa!(sv_u64, frame.op_stack_top) = dsa!(sv_u64, frame.stack_bottom + var as usize);
+    frame.op_stack_top += 1;
+    Ok(())
+}
+
+unsafe fn h_store(context: &mut Context, _: &Function, frame: &mut Frame, var: u64) -> Result<(), VmError> {
+    let sv_u64 = context.stack as *mut u64;
+    dsa!(sv_u64, frame.stack_bottom + var as usize) = dsa!(sv_u64, frame.op_stack_top - 1);
+    frame.op_stack_top -= 1;
+    Ok(())
+}
+
+/// Executes the instruction at the index with the shared semantics.
+unsafe fn h_execute(context: &mut Context, function: &Function, frame: &mut Frame,
+                    index: u64) -> Result<(), VmError> {
+    match function.instructions {
+        Instructions::Bytecode(ref insts) => context.execute_instruction(function, &insts[index as usize], frame),
+        Instructions::File {..} => Err(VmError::BytecodeNotLoaded { function: function.name.clone() }),
+    }
+}
+
+unsafe fn h_cst_bits(context: &mut Context, _: &Function, frame: &mut Frame, bits: u64) -> Result<(), VmError> {
+    let sv_u64 = context.stack as *mut u64;
+    dsa!(sv_u64, frame.op_stack_top) = bits;
+    frame.op_stack_top += 1;
+    Ok(())
+}
+
+/// Applies a binary operation to the type in the operand.
+macro_rules! typed_handler {
+    ( $name:ident, $op:tt, $method:ident, $check_zero:expr ) => {
+        unsafe fn $name(context: &mut Context, _: &Function, frame: &mut Frame,
+                        t: u64) -> Result<(), VmError> {
+            let t = &Type::from_u64(t).unwrap();
+            let sv_u64 = context.stack as *mut u64;
+            let mut op_stack_top = frame.op_stack_top;
+            match_op!(context.heap, sv_u64, t, op_stack_top, $op, $method, $check_zero);
+            frame.op_stack_top = op_stack_top;
+            Ok(())
+        }
+    };
+}
+
+typed_handler!(h_add, +, wrapping_add, false);
+typed_handler!(h_sub, -, wrapping_sub, false);
+typed_handler!(h_mul, *, wrapping_mul, false);
+typed_handler!(h_div, /, wrapping_div, true);
+typed_handler!(h_rem, %, wrapping_rem, true);
+
+/// Monomorphic integer arithmetic.
+macro_rules! int_handler {
+    ( $name:ident, $t:ty, $method:ident, $check_zero:expr ) => {
+        unsafe fn $name(context: &mut Context, _: &Function, frame: &mut Frame,
+                        _: u64) -> Result<(), VmError> {
+            let mut op_stack_top = frame.op_stack_top;
+            int_stack_op!($t, context.stack as *mut u64, op_stack_top, $method, $check_zero);
+            frame.op_stack_top = op_stack_top;
+            Ok(())
+        }
+    };
+}
+
+/// Monomorphic float arithmetic.
+macro_rules! float_handler {
+    ( $name:ident, $t:ty, $op:tt ) => {
+        unsafe fn $name(context: &mut Context, _: &Function, frame: &mut Frame,
+                        _: u64) -> Result<(), VmError> {
+            let mut op_stack_top = frame.op_stack_top;
+            stack_op!($t, context.stack as *mut u64, op_stack_top, $op);
+            frame.op_stack_top = op_stack_top;
+            Ok(())
+        }
+    };
+}
+
+int_handler!(h_add_u64, u64, wrapping_add, false);
+int_handler!(h_add_u32, u32, wrapping_add, false);
+int_handler!(h_add_i64, i64, wrapping_add, false);
+int_handler!(h_add_i32, i32, wrapping_add, false);
+float_handler!(h_add_f64, f64, +);
+float_handler!(h_add_f32, f32, +);
+
+int_handler!(h_sub_u64, u64, wrapping_sub, false);
+int_handler!(h_sub_u32, u32, wrapping_sub, false);
+int_handler!(h_sub_i64, i64, wrapping_sub, false);
+int_handler!(h_sub_i32, i32, wrapping_sub, false);
+float_handler!(h_sub_f64, f64, -);
+float_handler!(h_sub_f32, f32, -);
+
+int_handler!(h_mul_u64, u64, wrapping_mul, false);
+int_handler!(h_mul_u32, u32, wrapping_mul, false);
+int_handler!(h_mul_i64, i64, wrapping_mul, false);
+int_handler!(h_mul_i32, i32, wrapping_mul, false);
+float_handler!(h_mul_f64, f64, *);
+float_handler!(h_mul_f32, f32, *);
+
+int_handler!(h_div_u64, u64, wrapping_div, true);
+int_handler!(h_div_u32, u32, wrapping_div, true);
+int_handler!(h_div_i64, i64, wrapping_div, true);
+int_handler!(h_div_i32, i32, wrapping_div, true);
+float_handler!(h_div_f64, f64, /);
+float_handler!(h_div_f32, f32, /);
+
+int_handler!(h_rem_u64, u64, wrapping_rem, true);
+int_handler!(h_rem_u32, u32, wrapping_rem, true);
+int_handler!(h_rem_i64, i64, wrapping_rem, true);
+int_handler!(h_rem_i32, i32, wrapping_rem, true);
+float_handler!(h_rem_f64, f64, %);
+float_handler!(h_rem_f32, f32, %);
+
+impl RegisterCode {
+    /// Translates the bytecode of the function, which must be loaded and
+    /// should be specialized. Returns None if the operand stack underflows.
+    pub fn translate(function: &Function) -> Option<RegisterCode> {
+        let insts = match function.instructions {
+            Instructions::Bytecode(ref vec) => vec,
+            Instructions::File {..} => panic!("The bytecode of function '{}' is not loaded.", function.name),
+        };
+
+        let mut translator = RegisterTranslator {
+            ops: Vec::with_capacity(insts.len()),
+            stack: Vec::new(),
+            locals_count: function.sizes.locals_count as usize,
+        };
+        for (index, inst) in insts.iter().enumerate() {
+            if !translator.translate(index, inst) {
+                return None;
+            }
+        }
+
+        Some(RegisterCode {
+            ops: translator.ops,
+            inst_count: insts.len(),
+        })
+    }
+}
+
+struct RegisterTranslator {
+    ops: Vec<RegisterOp>,
+
+    /// The register that holds the value at each depth of the operand stack.
+    stack: Vec<usize>,
+
+    locals_count: usize,
+}
+
+impl RegisterTranslator {
+    /// The register of the operand stack slot at the depth.
+    fn slot(&self, depth: usize) -> usize {
+        self.locals_count + depth
+    }
+
+    fn emit(&mut self, handler: RegisterHandler, inst_index: usize, dst: usize,
+            a: usize, b: usize, operand: u64) {
+        self.ops.push(RegisterOp {
+            handler: handler,
+            inst_index: inst_index,
+            dst: dst,
+            a: a,
+            b: b,
+            operand: operand,
+        });
+    }
+
+    /// Copies values that are held in other registers into their slots.
+    /// Only copies values held in the register, if one is given.
+    fn materialize(&mut self, inst_index: usize, register: Option<usize>) {
+        for depth in 0..self.stack.len() {
+            let held = self.stack[depth];
+            let slot = self.slot(depth);
+            if held != slot && register.map_or(true, |register| register == held) {
+                self.emit(r_move, inst_index, slot, held, 0, 0);
+                self.stack[depth] = slot;
+            }
+        }
+    }
+
+    /// Returns false if the operand stack underflows.
+    fn translate(&mut self, index: usize, inst: &Instruction) -> bool {
+        let (pops, pushes) = inst.stack_effect();
+        if pops > self.stack.len() {
+            return false;
+        }
+
+        let binary: Option<RegisterHandler> = match *inst {
+                Instruction::AddU64 => Some(r_add_u64),
+                Instruction::AddU32 => Some(r_add_u32),
+                Instruction::AddI64 => Some(r_add_i64),
+                Instruction::AddI32 => Some(r_add_i32),
+                Instruction::AddF64 => Some(r_add_f64),
+                Instruction::AddF32 => Some(r_add_f32),
+                Instruction::SubU64 => Some(r_sub_u64),
+                Instruction::SubU32 => Some(r_sub_u32),
+                Instruction::SubI64 => Some(r_sub_i64),
+                Instruction::SubI32 => Some(r_sub_i32),
+                Instruction::SubF64 => Some(r_sub_f64),
+                Instruction::SubF32 => Some(r_sub_f32),
+                Instruction::MulU64 => Some(r_mul_u64),
+                Instruction::MulU32 => Some(r_mul_u32),
+                Instruction::MulI64 => Some(r_mul_i64),
+                Instruction::MulI32 => Some(r_mul_i32),
+                Instruction::MulF64 => Some(r_mul_f64),
+                Instruction::MulF32 => Some(r_mul_f32),
+                Instruction::DivU64 => Some(r_div_u64),
+                Instruction::DivU32 => Some(r_div_u32),
+                Instruction::DivI64 => Some(r_div_i64),
+                Instruction::DivI32 => Some(r_div_i32),
+                Instruction::DivF64 => Some(r_div_f64),
+                Instruction::DivF32 => Some(r_div_f32),
+                Instruction::RemU64 => Some(r_rem_u64),
+                Instruction::RemU32 => Some(r_rem_u32),
+                Instruction::RemI64 => Some(r_rem_i64),
+                Instruction::RemI32 => Some(r_rem_i32),
+                Instruction::RemF64 => Some(r_rem_f64),
+                Instruction::RemF32 => Some(r_rem_f32),
+                _ => None,
+            };
+
+        match *inst {
+            Instruction::Nop => { },
+            Instruction::Pop => { self.stack.pop(); },
+            Instruction::Dup => {
+                let top = *self.stack.last().unwrap();
+                self.stack.push(top);
+            },
+            Instruction::Load(var) => self.stack.push(var as usize),
+            Instruction::CstBits(_, bits) => {
+                let dst = self.slot(self.stack.len());
+                self.emit(r_cst_bits, index, dst, 0, 0, bits);
+                self.stack.push(dst);
+            },
+            Instruction::Store(var) => {
+                let var = var as usize;
+                let value = self.stack.pop().unwrap();
+
+                // Values that are still held in the local have to be saved
+                // before it is overwritten.
+                let shared = self.stack.contains(&var);
+                self.materialize(index, Some(var));
+
+                // The value was just computed into its slot, so the
+                // computation can write to the local instead.
+                let depth = self.stack.len();
+                let retarget = !shared && value == self.slot(depth) && match self.ops.last() {
+                    Some(op) => op.dst == value && op.handler as usize != r_stack as usize,
+                    None => false,
+                };
+                if retarget {
+                    self.ops.last_mut().unwrap().dst = var;
+                } else if value != var {
+                    self.emit(r_move, index, var, value, 0, 0);
+                }
+            },
+            _ => {
+                if let Some(handler) = binary {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    let dst = self.slot(self.stack.len());
+                    self.emit(handler, index, dst, a, b, 0);
+                    self.stack.push(dst);
+                } else {
+                    self.materialize(index, None);
+                    let depth = self.stack.len();
+                    self.emit(r_stack, index, 0, 0, 0, depth as u64);
+                    self.stack.truncate(depth - pops);
+                    for _ in 0..pushes {
+                        let slot = self.slot(self.stack.len());
+                        self.stack.push(slot);
+                    }
+                }
+            },
+        }
+        true
     }
 }
 
+unsafe fn r_move(context: &mut Context, _: &Function, frame: &mut Frame, op: &RegisterOp) -> Result<(), VmError> {
+    let sv_u64 = context.stack as *mut u64;
+    dsa!(sv_u64, frame.stack_bottom + op.dst) = dsa!(sv_u64, frame.stack_bottom + op.a);
+    Ok(())
+}
+
+/// Executes the instruction on the operand stack, which has the depth in the operand.
+unsafe fn r_stack(context: &mut Context, function: &Function, frame: &mut Frame,
+                  op: &RegisterOp) -> Result<(), VmError> {
+    frame.op_stack_top = frame.operands_bottom + op.operand as usize;
+    h_execute(context, function, frame, op.inst_index as u64)
+}
+
+unsafe fn r_cst_bits(context: &mut Context, _: &Function, frame: &mut Frame, op: &RegisterOp) -> Result<(), VmError> {
+    let sv_u64 = context.stack as *mut u64;
+    dsa!(sv_u64, frame.stack_bottom + op.dst) = op.operand;
+    Ok(())
+}
+
+/// Integer arithmetic on registers wraps around like int_stack_op.
+macro_rules! int_register_handler {
+    ( $name:ident, $t:ty, $method:ident, $check_zero:expr ) => {
+        unsafe fn $name(context: &mut Context, _: &Function, frame: &mut Frame,
+                        op: &RegisterOp) -> Result<(), VmError> {
+            let ptr = context.stack as *mut u64;
+            let bottom = frame.stack_bottom;
+            let b = tsr!($t, ptr, bottom + op.b);
+            if $check_zero && b == 0 {
+                return Err(VmError::DivisionByZero);
+            }
+            tsw!(ptr, bottom + op.dst, tsr!($t, ptr, bottom + op.a).$method(b));
+            Ok(())
+        }
+    };
+}
+
+macro_rules! float_register_handler {
+    ( $name:ident, $t:ty, $op:tt ) => {
+        unsafe fn $name(context: &mut Context, _: &Function, frame: &mut Frame,
+                        op: &RegisterOp) -> Result<(), VmError> {
+            let ptr = context.stack as *mut u64;
+            let bottom = frame.stack_bottom;
+            let result: $t = workaround_expr!(tsr!($t, ptr, bottom + op.a) $op tsr!($t, ptr, bottom + op.b));
+            tsw!(ptr, bottom + op.dst, result);
+            Ok(())
+        }
+    };
+}
+
+int_register_handler!(r_add_u64, u64, wrapping_add, false);
+int_register_handler!(r_add_u32, u32, wrapping_add, false);
+int_register_handler!(r_add_i64, i64, wrapping_add, false);
+int_register_handler!(r_add_i32, i32, wrapping_add, false);
+float_register_handler!(r_add_f64, f64, +);
+float_register_handler!(r_add_f32, f32, +);
+
+int_register_handler!(r_sub_u64, u64, wrapping_sub, false);
+int_register_handler!(r_sub_u32, u32, wrapping_sub, false);
+int_register_handler!(r_sub_i64, i64, wrapping_sub, false);
+int_register_handler!(r_sub_i32, i32, wrapping_sub, false);
+float_register_handler!(r_sub_f64, f64, -);
+float_register_handler!(r_sub_f32, f32, -);
+
+int_register_handler!(r_mul_u64, u64, wrapping_mul, false);
+int_register_handler!(r_mul_u32, u32, wrapping_mul, false);
+int_register_handler!(r_mul_i64, i64, wrapping_mul, false);
+int_register_handler!(r_mul_i32, i32, wrapping_mul, false);
+float_register_handler!(r_mul_f64, f64, *);
+float_register_handler!(r_mul_f32, f32, *);
+
+int_register_handler!(r_div_u64, u64, wrapping_div, true);
+int_register_handler!(r_div_u32, u32, wrapping_div, true);
+int_register_handler!(r_div_i64, i64, wrapping_div, true);
+int_register_handler!(r_div_i32, i32, wrapping_div, true);
+float_register_handler!(r_div_f64, f64, /);
+float_register_handler!(r_div_f32, f32, /);
+
+int_register_handler!(r_rem_u64, u64, wrapping_rem, true);
+int_register_handler!(r_rem_u32, u32, wrapping_rem, true);
+int_register_handler!(r_rem_i64, i64, wrapping_rem, true);
+int_register_handler!(r_rem_i32, i32, wrapping_rem, true);
+float_register_handler!(r_rem_f64, f64, %);
+float_register_handler!(r_rem_f32, f32, %);
+
 impl Drop for Context {
     fn drop(&mut self) {
         unsafe { deallocate(self.stack, self.stack_size(), STACK_ALIGN) };
     }
 }
 
-/// Stack address offset.
-// TODO Inline?
-fn sao(t: Type, stack_index: usize) -> isize {
+fn constant(function: &Function, index: ConstantTableIndex) -> Result<&Constant, VmError> {
+    match function.constant_table.table.get(index as usize) {
+        Some(constant) => Ok(constant),
+        None => Err(VmError::InvalidConstant { index: index, expected: "an existing constant" }),
+    }
+}
+
+/// Creates a trap with the Str message at the given constant index.
+fn trap(function: &Function, index: ConstantTableIndex, inst_index: usize) -> VmError {
+    match constant(function, index) {
+        Ok(&Constant::Str(ref string)) => {
+            VmError::Trap(Trap::new(string.clone(), function.name.clone(), inst_index))
+        },
+        Ok(..) => VmError::InvalidConstant { index: index, expected: "a string" },
+        Err(error) => error,
+    }
+}
+
+/// Unordered floats compare as greater.
+fn ordering_value(ordering: Option<Ordering>) -> i32 {
+    match ordering {
+        Some(Ordering::Less) => -1,
+        Some(Ordering::Equal) => 0,
+        Some(Ordering::Greater) | None => 1,
+    }
+}
+
+/// Checks that the instruction only accesses operands and locals of the
+/// frame, and that the operand stack stays within its maximum size.
+fn check_access(function: &Function, inst: &Instruction, frame: &Frame) -> Result<(), VmError> {
+    let depth = frame.op_stack_top - frame.operands_bottom;
+    let (pops, pushes) = inst.stack_effect();
+
+    // Ret reads its results without popping them.
+    let reads = match *inst {
+        Instruction::Ret(count) => count as usize,
+        _ => pops,
+    };
+    if reads > depth {
+        return Err(VmError::OperandStackUnderflow { depth: depth, required: reads });
+    }
+    if depth - pops + pushes > function.sizes.max_operands as usize {
+        return Err(VmError::OperandStackOverflow { max_operands: function.sizes.max_operands as usize });
+    }
+
+    match *inst {
+        Instruction::Load(local) | Instruction::Store(local) => {
+            let locals_count = frame.operands_bottom - frame.stack_bottom;
+            if local as usize >= locals_count {
+                return Err(VmError::InvalidLocal { local: local, locals_count: locals_count });
+            }
+        },
+        Instruction::Ret(count) => {
+            let return_count = function.sizes.return_count as usize;
+            if count as usize > return_count {
+                return Err(VmError::ReturnCountMismatch { expected: return_count, actual: count as usize });
+            }
+        },
+        _ => { },
+    }
+    Ok(())
+}
+
+/// The float type of the result that the instruction pushes, if any.
+fn float_result(inst: &Instruction) -> Option<Type> {
+    match *inst {
+        Instruction::Add(t) | Instruction::Sub(t) | Instruction::Mul(t) |
+        Instruction::Div(t) | Instruction::Rem(t) | Instruction::Conv(_, t)
+            if t == Type::F64 || t == Type::F32 => Some(t),
+        Instruction::AddF64 | Instruction::SubF64 | Instruction::MulF64 |
+        Instruction::DivF64 | Instruction::RemF64 => Some(Type::F64),
+        Instruction::AddF32 | Instruction::SubF32 | Instruction::MulF32 |
+        Instruction::DivF32 | Instruction::RemF32 => Some(Type::F32),
+        _ => None,
+    }
+}
+
+/// The float type of the constant that a Cst instruction pushes, if any.
+fn float_constant(function: &Function, inst: &Instruction) -> Option<Type> {
+    let index = match *inst {
+        Instruction::Cst(index) | Instruction::CstBits(index, _) => index,
+        _ => return None,
+    };
+    match function.constant_table.table.get(index as usize) {
+        Some(&Constant::F64(..)) => Some(Type::F64),
+        Some(&Constant::F32(..)) => Some(Type::F32),
+        _ => None,
+    }
+}
+
+/// Replaces any NaN with the canonical quiet NaN of the float type.
+fn canonical_float_slot(t: Type, slot: u64) -> u64 {
     match t {
-        Type::U64 | Type::I64 | Type::F64 => stack_index as isize,
-        Type::U32 | Type::I32 | Type::F32 => (stack_index as isize) * 2 + 1,
-        _ => panic!("Address could not be calculated!"),
+        Type::F64 if f64::from_slot(slot).is_nan() => 0x7ff8000000000000,
+        Type::F32 if f32::from_slot(slot).is_nan() => 0x7fc00000,
+        _ => slot,
     }
 }
+
+/// Checks typed arguments against the signature of the function.
+fn check_arguments(function: &Function, arguments: &[Value]) -> Result<(), VmError> {
+    let signature = match function.signature {
+        Some(ref signature) if signature.fits(&function.sizes) => signature,
+        _ => return Err(VmError::InvalidSignature { function: function.name.clone() }),
+    };
+
+    if arguments.len() != signature.arguments.len() {
+        return Err(VmError::ArgumentCountMismatch {
+            function: function.name.clone(),
+            expected: signature.arguments.len(),
+            actual: arguments.len(),
+        });
+    }
+
+    for (i, (argument, expected)) in arguments.iter().zip(&signature.arguments).enumerate() {
+        if argument.value_type() != *expected {
+            return Err(VmError::ArgumentTypeMismatch {
+                function: function.name.clone(),
+                index: i,
+                expected: *expected,
+                actual: argument.value_type(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The raw bits of the stack slot that holds the value. Big integers are
+/// allocated on the heap.
+fn value_slot(heap: &mut Heap, value: Value) -> Result<u64, VmError> {
+    let slot = match value {
+        Value::I8(num) => num.to_slot(),
+        Value::I16(num) => num.to_slot(),
+        Value::I32(num) => num.to_slot(),
+        Value::I64(num) => num.to_slot(),
+        Value::U8(num) => num.to_slot(),
+        Value::U16(num) => num.to_slot(),
+        Value::U32(num) => num.to_slot(),
+        Value::U64(num) => num.to_slot(),
+        Value::F32(num) => num.to_slot(),
+        Value::F64(num) => num.to_slot(),
+        Value::BigInt(num) => try!(heap.allocate_big_int(num)),
+    };
+    Ok(slot)
+}
+
+/// Reads the value of the type from the raw bits of a stack slot.
+fn slot_value(heap: &Heap, t: Type, slot: u64) -> Result<Value, VmError> {
+    let value = match t {
+        Type::I8 => Value::I8(SlotValue::from_slot(slot)),
+        Type::I16 => Value::I16(SlotValue::from_slot(slot)),
+        Type::I32 => Value::I32(SlotValue::from_slot(slot)),
+        Type::I64 => Value::I64(SlotValue::from_slot(slot)),
+        Type::U8 => Value::U8(SlotValue::from_slot(slot)),
+        Type::U16 => Value::U16(SlotValue::from_slot(slot)),
+        Type::U32 => Value::U32(SlotValue::from_slot(slot)),
+        Type::U64 => Value::U64(SlotValue::from_slot(slot)),
+        Type::F32 => Value::F32(SlotValue::from_slot(slot)),
+        Type::F64 => Value::F64(SlotValue::from_slot(slot)),
+        Type::BigInt => match heap.big_int(slot) {
+            Some(num) => Value::BigInt(num.clone()),
+            None => return Err(VmError::InvalidHandle(slot)),
+        },
+        Type::Ptr | Type::Void => return Err(VmError::UnsupportedType(t)),
+    };
+    Ok(value)
+}
+
+/// The raw bits of the stack slot that Cst fills with the constant, for all
+/// constants that are not allocated on the heap and can be pushed.
+pub fn constant_slot_bits(constant: &Constant) -> Option<u64> {
+    let bits = match *constant {
+        Constant::U64(num) => num.to_slot(),
+        Constant::U32(num) => num.to_slot(),
+        Constant::I64(num) => num.to_slot(),
+        Constant::I32(num) => num.to_slot(),
+        Constant::F64(num) => num.to_slot(),
+        Constant::F32(num) => num.to_slot(),
+        Constant::U16(num) => num.to_slot(),
+        Constant::U8(num) => num.to_slot(),
+        Constant::I16(num) => num.to_slot(),
+        Constant::I8(num) => num.to_slot(),
+        Constant::Char(c) => (c as u32).to_slot(),
+        Constant::BigInt(..) | Constant::Str(..) | Constant::Bytes(..) => return None,
+    };
+    Some(bits)
+}
+
+/// How a value is represented in a stack slot. The representation does not
+/// depend on the endianness of the machine: integers are sign- or
+/// zero-extended to 64 bits, like 'as u64' does, and floats are stored as
+/// their bits, with f32 in the lower half.
+trait SlotValue: Copy {
+    fn from_slot(slot: u64) -> Self;
+    fn to_slot(self) -> u64;
+}
+
+macro_rules! int_slot_value {
+    ( $( $t:ty ),* ) => {
+        $(
+            impl SlotValue for $t {
+                #[inline(always)]
+                fn from_slot(slot: u64) -> $t {
+                    slot as $t
+                }
+
+                #[inline(always)]
+                fn to_slot(self) -> u64 {
+                    self as u64
+                }
+            }
+        )*
+    };
+}
+
+int_slot_value!(u64, u32, u16, u8, i64, i32, i16, i8);
+
+impl SlotValue for f64 {
+    #[inline(always)]
+    fn from_slot(slot: u64) -> f64 {
+        f64::from_bits(slot)
+    }
+
+    #[inline(always)]
+    fn to_slot(self) -> u64 {
+        self.to_bits()
+    }
+}
+
+impl SlotValue for f32 {
+    #[inline(always)]
+    fn from_slot(slot: u64) -> f32 {
+        f32::from_bits(slot as u32)
+    }
+
+    #[inline(always)]
+    fn to_slot(self) -> u64 {
+        self.to_bits() as u64
+    }
+}
+
+/// The type ID in a stack slot. Slots beyond the range of IDs saturate to
+/// u32::MAX, which is never registered, instead of wrapping to a valid ID.
+fn slot_type_id(slot: u64) -> NamedTypeId {
+    cmp::min(slot, u32::max_value() as u64) as NamedTypeId
+}
@@ -1,10 +1,11 @@
-use std::io::{Read, BufReader, Result};
+use std::io::{Cursor, Read, BufReader, Result};
 use std::path::{Path, PathBuf};
 use std::fs::File;
 
 use byteorder::{BigEndian, ReadBytesExt};
 
 use bytecode::Constant;
+use scribe::ConstantTableWriter;
 
 
 pub struct ConstantTable {
@@ -38,4 +39,12 @@ impl ConstantTable {
 
         Ok(ConstantTable { table: table })
     }
+
+    /// Encodes the table in the file format, which tells apart all constants
+    /// that differ, including signed zeros and NaN payloads.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+        ConstantTableWriter::new(&mut cursor).write_constant_table(self);
+        cursor.into_inner()
+    }
 }
@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+
+use bytecode::VariableIndex;
+
+
+/// Breakpoints and watchpoints of a Context. Execution is suspended when one
+/// of them is hit and can be resumed or stepped through afterwards. A Context
+/// only checks them while a debugger is attached, so that execution without
+/// a debugger does not pay for them.
+pub struct Debugger {
+    /// Pairs of function IDs and instruction indices. Execution is suspended
+    /// before the instruction executes.
+    breakpoints: HashSet<(u32, usize)>,
+
+    /// Pairs of function IDs and local indices. Execution is suspended after
+    /// an instruction has stored to the local.
+    watchpoints: HashSet<(u32, VariableIndex)>,
+}
+
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+        }
+    }
+
+    pub fn set_breakpoint(&mut self, function_id: u32, inst_index: usize) {
+        self.breakpoints.insert((function_id, inst_index));
+    }
+
+    /// Returns whether the breakpoint was set.
+    pub fn clear_breakpoint(&mut self, function_id: u32, inst_index: usize) -> bool {
+        self.breakpoints.remove(&(function_id, inst_index))
+    }
+
+    pub fn has_breakpoint(&self, function_id: u32, inst_index: usize) -> bool {
+        self.breakpoints.contains(&(function_id, inst_index))
+    }
+
+    pub fn set_watchpoint(&mut self, function_id: u32, local: VariableIndex) {
+        self.watchpoints.insert((function_id, local));
+    }
+
+    /// Returns whether the watchpoint was set.
+    pub fn clear_watchpoint(&mut self, function_id: u32, local: VariableIndex) -> bool {
+        self.watchpoints.remove(&(function_id, local))
+    }
+
+    pub fn is_watched(&self, function_id: u32, local: VariableIndex) -> bool {
+        self.watchpoints.contains(&(function_id, local))
+    }
+}
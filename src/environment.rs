@@ -1,10 +1,15 @@
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::{Seek, SeekFrom};
 
+use context::{RegisterCode, ThreadedCode};
 use function::{INVALID_FUNCTION_ID, Function, Instructions};
 use cst::ConstantTable;
+use error::VmError;
+use hierarchy::{NamedType, TypeDeclaration, TypeHierarchy};
 
 
 /// Currently NOT thread-safe. TODO How is that in Rust, even?
@@ -12,8 +17,15 @@ pub struct Environment {
     functions: Vec<Function>,
     function_names_to_ids: HashMap<String, u32>,
 
-    /// The String is the path (without file extension) to the constant table.
-    constant_tables: HashMap<String, Arc<ConstantTable>>,
+    /// Keyed by the path (without file extension) to the constant table.
+    constant_tables: HashMap<PathBuf, Arc<ConstantTable>>,
+
+    /// The constant tables of fetched functions, keyed by a hash of their
+    /// encoding, so that functions with equal tables share one.
+    shared_tables: HashMap<u64, Vec<Arc<ConstantTable>>>,
+
+    /// Shared with contexts, which need it for type tests and casts.
+    types: Arc<TypeHierarchy>,
 }
 
 
@@ -24,6 +36,8 @@ impl Environment {
             functions: Vec::new(),
             function_names_to_ids: HashMap::new(),
             constant_tables: HashMap::new(),
+            shared_tables: HashMap::new(),
+            types: Arc::new(TypeHierarchy::new()),
         }
     }
 
@@ -48,16 +62,74 @@ impl Environment {
         next_id
     }
 
+    /// Registers a named type. The supertypes must already be registered.
+    /// Contexts that were handed the hierarchy before keep the old version.
+    pub fn register_type(&mut self, name: &str, supertypes: &[u32]) -> u32 {
+        Arc::make_mut(&mut self.types).register_type(name, supertypes)
+    }
+
+    /// Loads and registers all type declarations of a '.types' file, in order.
+    /// Returns the IDs of the registered types. Fails without registering any
+    /// type if the file cannot be read, if a type is already registered or if
+    /// a supertype is not.
+    pub fn load_types(&mut self, path: &Path) -> Result<Vec<u32>, VmError> {
+        let load = |message: String| VmError::LoadFailed {
+            name: path.display().to_string(),
+            message: message,
+        };
+
+        let declarations = try!(TypeDeclaration::from_file(path)
+            .map_err(|error| load(format!("Could not read type declarations: {}", error))));
+
+        let mut types = (*self.types).clone();
+        let mut ids = Vec::with_capacity(declarations.len());
+        for declaration in declarations {
+            if types.get_type_by_name(&declaration.name).is_some() {
+                return Err(load(format!("Type '{}' is already registered.", declaration.name)));
+            }
+
+            let mut supertypes = Vec::with_capacity(declaration.supertype_names.len());
+            for supertype_name in &declaration.supertype_names {
+                match types.get_type_by_name(supertype_name) {
+                    Some(supertype) => supertypes.push(supertype.id),
+                    None => return Err(load(format!("Supertype '{}' of type '{}' is not registered.",
+                        supertype_name, declaration.name))),
+                }
+            }
+            ids.push(types.register_type(&declaration.name[..], &supertypes[..]));
+        }
+
+        self.types = Arc::new(types);
+        Ok(ids)
+    }
+
+    pub fn get_type_by_name(&self, name: &str) -> Option<&NamedType> {
+        self.types.get_type_by_name(name)
+    }
+
+    pub fn is_subtype(&self, subtype: u32, supertype: u32) -> bool {
+        self.types.is_subtype(subtype, supertype)
+    }
+
+    pub fn type_hierarchy(&self) -> Arc<TypeHierarchy> {
+        self.types.clone()
+    }
+
     /// Loads the constant table on demand, or returns a cached version.
-    pub fn fetch_constant_table(&mut self, path: &Path) -> Arc<ConstantTable> {
-        let arc_opt = self.constant_tables.get(path.to_str().unwrap()).map(|arc| arc.clone());
+    /// Fails if the table cannot be read.
+    pub fn fetch_constant_table(&mut self, path: &Path) -> Result<Arc<ConstantTable>, VmError> {
+        let arc_opt = self.constant_tables.get(path).map(|arc| arc.clone());
         match arc_opt {
-            Some(arc) => arc,
+            Some(arc) => Ok(arc),
             None => {
                 // Load constant table, then register it and return it.
-                let arc = Arc::new(ConstantTable::from_file(path).unwrap());
-                self.constant_tables.insert(path.to_str().unwrap().to_string(), arc.clone());
-                arc
+                let table = try!(ConstantTable::from_file(path).map_err(|error| VmError::LoadFailed {
+                    name: path.display().to_string(),
+                    message: format!("Could not read constant table: {}", error),
+                }));
+                let arc = Arc::new(table);
+                self.constant_tables.insert(path.to_path_buf(), arc.clone());
+                Ok(arc)
             },
         }
     }
@@ -68,6 +140,12 @@ impl Environment {
         return &self.functions[id as usize];
     }
 
+    /// Returns None if the function does not exist.
+    /// Does NOT load the bytecode.
+    pub fn find_function_by_id(&self, id: u32) -> Option<&Function> {
+        self.functions.get(id as usize)
+    }
+
     /// Does NOT load the bytecode.
     pub fn get_function_by_name(&self, name: &str) -> Option<&Function> {
         match self.function_names_to_ids.get(name) {
@@ -76,27 +154,34 @@ impl Environment {
         }
     }
 
-    /// Loads the instructions of the function if they are not already loaded.
-    pub fn fetch_function_by_id(&mut self, id: u32) -> &Function {
-        let function = &mut self.functions[id as usize];
+    /// Loads the instructions of the function if they are not already loaded,
+    /// resolves constants, specializes typed arithmetic and translates the
+    /// instructions into threaded and register code. The constant table is
+    /// replaced by an equal one that another fetched function uses, if any.
+    /// Fails if the function does not exist, if its file cannot be read or if
+    /// the instructions refer to invalid constants.
+    pub fn fetch_function_by_id(&mut self, id: u32) -> Result<&Function, VmError> {
+        let function = match self.functions.get_mut(id as usize) {
+            Some(function) => function,
+            None => return Err(VmError::LoadFailed {
+                name: format!("#{}", id),
+                message: "The function does not exist.".to_string(),
+            }),
+        };
+
         let instructions_option = match function.instructions {
             Instructions::File { ref path, ref offset } => {
-                let result = Function::open_reader(path.as_path());
-                if result.is_err() {
-                    panic!("Could not open reader for file '{:?}'.", path);
-                }
-
-                let mut reader = result.unwrap();
-                let result = reader.seek(SeekFrom::Start(*offset));
-                if result.is_err() {
-                    panic!("Could not seek to the appropriate place for file '{:?}'.", path);
-                }
-
-                let result = Instructions::from_read(&mut reader);
-                if result.is_err() {
-                    panic!("Instructions from file '{:?}' could not be loaded.", path);
-                }
-                Some(result.unwrap())
+                let load = |message: &str| VmError::LoadFailed {
+                    name: function.name.clone(),
+                    message: format!("{} '{:?}'.", message, path),
+                };
+
+                let mut reader = try!(Function::open_reader(path.as_path())
+                    .map_err(|_| load("Could not open reader for file")));
+                try!(reader.seek(SeekFrom::Start(*offset))
+                    .map_err(|_| load("Could not seek to the appropriate place for file")));
+                Some(try!(Instructions::from_read(&mut reader)
+                    .map_err(|_| load("Could not read instructions from file"))))
             },
             Instructions::Bytecode(..) => {
                 // We're good, no need to load anything!
@@ -111,7 +196,39 @@ impl Environment {
             None => { },
         };
 
-        function
+        // All of this happens once, after the bytecode has been loaded.
+        if function.threaded_code.is_none() {
+            try!(function.resolve_constants());
+            function.constant_table = share_constant_table(&mut self.shared_tables, &function.constant_table);
+            if let Instructions::Bytecode(ref mut insts) = function.instructions {
+                for inst in insts.iter_mut() {
+                    *inst = inst.specialize();
+                }
+            }
+            function.threaded_code = Some(ThreadedCode::translate(function));
+            function.register_code = RegisterCode::translate(function);
+            function.fingerprint = function.bytecode_fingerprint();
+        }
+
+        Ok(function)
     }
 
 }
+
+/// Returns the shared table that is equal to the given one, which becomes
+/// shared if there is none.
+fn share_constant_table(shared_tables: &mut HashMap<u64, Vec<Arc<ConstantTable>>>,
+                        table: &Arc<ConstantTable>) -> Arc<ConstantTable> {
+    let bytes = table.to_bytes();
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+
+    let candidates = shared_tables.entry(hasher.finish()).or_insert_with(Vec::new);
+    for candidate in candidates.iter() {
+        if Arc::ptr_eq(candidate, table) || candidate.to_bytes() == bytes {
+            return candidate.clone();
+        }
+    }
+    candidates.push(table.clone());
+    table.clone()
+}
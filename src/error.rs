@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::fmt;
+
+use bytecode::{ConstantTableIndex, NamedTypeId, Type, VariableIndex};
+use environment::Environment;
+
+
+/// Everything that can go wrong while a Context runs a function. The Context
+/// stays usable after an error.
+#[derive(Debug)]
+pub enum VmError {
+    /// The function has not been registered with an environment.
+    UnregisteredFunction { function: String },
+
+    ArgumentCountMismatch { function: String, expected: usize, actual: usize },
+
+    /// The function has no signature, or one that does not fit its sizes, so
+    /// it cannot be run with typed values.
+    InvalidSignature { function: String },
+
+    /// The argument at index does not have the type declared by the signature.
+    ArgumentTypeMismatch { function: String, index: usize, expected: Type, actual: Type },
+
+    /// The function needs more stack elements than the context has.
+    StackOverflow { function: String, required: usize, available: usize },
+
+    /// An allocation on the stack, on the heap or of a runtime string would
+    /// exceed the memory budget of the context, or the allocator could not
+    /// provide it. Then nothing is available.
+    OutOfMemory { requested: usize, available: usize },
+
+    /// The function, constant table or type declarations with the name do
+    /// not exist or could not be read.
+    LoadFailed { name: String, message: String },
+
+    /// The instructions of the function have not been fetched from their file.
+    BytecodeNotLoaded { function: String },
+
+    /// The instruction does not support the type.
+    UnsupportedType(Type),
+
+    /// The constant does not exist or does not have the expected kind, such
+    /// as "a number" or "a string".
+    InvalidConstant { index: ConstantTableIndex, expected: &'static str },
+
+    DivisionByZero,
+
+    /// The value cannot be represented in the target type of a conversion.
+    ConversionOverflow { value: String, to: Type },
+
+    CastFailed { from: NamedTypeId, to: NamedTypeId },
+
+    /// A stack element does not refer to a heap object.
+    InvalidHandle(u64),
+
+    /// The format template of a 'printfmt' is malformed or does not fit the arguments.
+    InvalidFormat(String),
+
+    Trap(Trap),
+
+    /// Execution has been suspended and can be resumed after adding fuel.
+    OutOfFuel { consumed: u64 },
+
+    /// Execution has been suspended through an InterruptHandle and can be resumed.
+    Interrupted,
+
+    /// Execution has been suspended before the instruction at a breakpoint.
+    Breakpoint { function_id: u32, inst_index: usize },
+
+    /// Execution has been suspended after the instruction at inst_index has
+    /// stored to a watched local.
+    Watchpoint { function_id: u32, local: VariableIndex, inst_index: usize },
+
+    /// Reported in checked mode when an instruction needs more operands than
+    /// the operand stack of the frame holds.
+    OperandStackUnderflow { depth: usize, required: usize },
+
+    /// Reported in checked mode when an instruction pushes beyond the
+    /// maximum operand stack size of the function.
+    OperandStackOverflow { max_operands: usize },
+
+    /// Reported in checked mode when a local does not exist in the frame.
+    InvalidLocal { local: VariableIndex, locals_count: usize },
+
+    /// Reported in checked mode when 'ret' returns more results than the
+    /// function declares.
+    ReturnCountMismatch { expected: usize, actual: usize },
+
+    /// The bytes are not a snapshot, or the snapshot does not fit the environment.
+    InvalidSnapshot(String),
+
+    /// The bytecode of the function differs from the bytecode it had when
+    /// the snapshot was taken.
+    SnapshotMismatch { function: String },
+
+    /// There is no suspended execution to resume.
+    NotSuspended,
+
+    /// The suspended execution belongs to another function. Holds the IDs of
+    /// the suspended function and of the function that was given.
+    ResumeMismatch { expected: u32, actual: u32 },
+}
+
+/// A VM error together with the frame chain that was active when it occurred.
+#[derive(Debug)]
+pub struct Fault {
+    pub error: VmError,
+    pub trace: StackTrace,
+}
+
+#[derive(Clone, Debug)]
+pub struct StackTrace {
+    /// The innermost frame comes first. Empty if the error occurred before
+    /// the first call.
+    pub frames: Vec<TraceFrame>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    pub function_name: String,
+    pub function_id: u32,
+
+    /// The index of the instruction that was executing.
+    pub inst_index: usize,
+}
+
+/// Raised by bytecode that stops execution on purpose, for example a failed
+/// 'assert' or an 'abort'.
+#[derive(Debug)]
+pub struct Trap {
+    pub message: String,
+
+    /// The function that raised the trap.
+    pub function_name: String,
+
+    /// The index of the instruction that raised the trap.
+    pub inst_index: usize,
+}
+
+
+impl VmError {
+    /// Whether the execution has been suspended rather than aborted, so that
+    /// it can be resumed.
+    pub fn is_suspension(&self) -> bool {
+        match *self {
+            VmError::OutOfFuel {..} | VmError::Interrupted |
+            VmError::Breakpoint {..} | VmError::Watchpoint {..} => true,
+            _ => false,
+        }
+    }
+}
+
+impl Fault {
+    pub fn new(error: VmError, trace: StackTrace) -> Fault {
+        Fault {
+            error: error,
+            trace: trace,
+        }
+    }
+
+    /// Formats the error and the stack trace. Each frame shows the instructions
+    /// within radius of the executing instruction, if the function is loaded
+    /// and exists in the environment.
+    pub fn disassemble(&self, environment: &Environment, radius: usize) -> String {
+        let mut output = format!("{}\n", self.error);
+        for frame in &self.trace.frames {
+            output.push_str(&format!("{}\n", frame));
+            match environment.find_function_by_id(frame.function_id) {
+                Some(function) => output.push_str(&function.disassemble_around(frame.inst_index, radius)[..]),
+                None => output.push_str("        <unknown function>\n"),
+            }
+        }
+        output
+    }
+}
+
+impl From<VmError> for Fault {
+    /// For errors that occur outside of any call.
+    fn from(error: VmError) -> Fault {
+        Fault::new(error, StackTrace::new())
+    }
+}
+
+impl StackTrace {
+    pub fn new() -> StackTrace {
+        StackTrace { frames: Vec::new() }
+    }
+}
+
+impl Trap {
+    pub fn new(message: String, function_name: String, inst_index: usize) -> Trap {
+        Trap {
+            message: message,
+            function_name: function_name,
+            inst_index: inst_index,
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Trap in function '{}' at instruction {}: {}",
+            self.function_name, self.inst_index, self.message)
+    }
+}
+
+impl Error for Trap {
+    fn description(&self) -> &str {
+        &self.message[..]
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::UnregisteredFunction { ref function } =>
+                write!(f, "Function '{}' must be registered with an environment.", function),
+            VmError::ArgumentCountMismatch { ref function, ref expected, ref actual } =>
+                write!(f, "Function '{}' expects {} arguments but got {}.", function, expected, actual),
+            VmError::InvalidSignature { ref function } =>
+                write!(f, "Function '{}' does not declare a signature that fits its sizes.", function),
+            VmError::ArgumentTypeMismatch { ref function, ref index, ref expected, ref actual } =>
+                write!(f, "Argument {} of function '{}' must be of type {:?}, but got {:?}.",
+                    index, function, expected, actual),
+            VmError::StackOverflow { ref function, ref required, ref available } =>
+                write!(f, "Stack overflow in function '{}': {} elements required, but only {} available.",
+                    function, required, available),
+            VmError::OutOfMemory { ref requested, ref available } =>
+                write!(f, "Out of memory: {} bytes requested, but only {} available.", requested, available),
+            VmError::LoadFailed { ref name, ref message } =>
+                write!(f, "'{}' could not be loaded: {}", name, message),
+            VmError::BytecodeNotLoaded { ref function } =>
+                write!(f, "Bytecode expected, but got file path for function '{}'.", function),
+            VmError::UnsupportedType(ref t) => write!(f, "Unsupported type {:?}.", t),
+            VmError::InvalidConstant { ref index, ref expected } =>
+                write!(f, "Constant at index {} must be {}.", index, expected),
+            VmError::DivisionByZero => write!(f, "Division by zero."),
+            VmError::ConversionOverflow { ref value, ref to } =>
+                write!(f, "{} cannot be converted to type {:?}.", value, to),
+            VmError::CastFailed { ref from, ref to } =>
+                write!(f, "Type {} cannot be cast to type {}.", from, to),
+            VmError::InvalidHandle(ref handle) => write!(f, "Invalid heap handle {}.", handle),
+            VmError::InvalidFormat(ref message) => write!(f, "{}", message),
+            VmError::Trap(ref trap) => trap.fmt(f),
+            VmError::OutOfFuel { ref consumed } =>
+                write!(f, "Out of fuel after consuming {} units.", consumed),
+            VmError::Interrupted => write!(f, "Execution has been interrupted."),
+            VmError::Breakpoint { ref function_id, ref inst_index } =>
+                write!(f, "Hit the breakpoint at instruction {} of function #{}.", inst_index, function_id),
+            VmError::Watchpoint { ref function_id, ref local, ref inst_index } =>
+                write!(f, "Instruction {} of function #{} stored to watched local {}.",
+                       inst_index, function_id, local),
+            VmError::OperandStackUnderflow { ref depth, ref required } =>
+                write!(f, "Operand stack underflow: {} operands required, but only {} available.",
+                    required, depth),
+            VmError::OperandStackOverflow { ref max_operands } =>
+                write!(f, "Operand stack overflow: at most {} operands are allowed.", max_operands),
+            VmError::InvalidLocal { ref local, ref locals_count } =>
+                write!(f, "Local {} does not exist, since the frame has {} locals.", local, locals_count),
+            VmError::ReturnCountMismatch { ref expected, ref actual } =>
+                write!(f, "Function returns {} results, but declares {}.", actual, expected),
+            VmError::InvalidSnapshot(ref message) => write!(f, "Invalid snapshot: {}", message),
+            VmError::SnapshotMismatch { ref function } =>
+                write!(f, "The bytecode of function '{}' has changed since the snapshot was taken.", function),
+            VmError::NotSuspended => write!(f, "There is no suspended execution to resume."),
+            VmError::ResumeMismatch { ref expected, ref actual } =>
+                write!(f, "The suspended execution belongs to function #{}, not #{}.", expected, actual),
+        }
+    }
+}
+
+impl Error for VmError {
+    fn description(&self) -> &str {
+        match *self {
+            VmError::Trap(ref trap) => trap.description(),
+            _ => "virtual machine error",
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.error));
+        for frame in &self.trace.frames {
+            try!(write!(f, "\n{}", frame));
+        }
+        Ok(())
+    }
+}
+
+impl Error for Fault {
+    fn description(&self) -> &str {
+        self.error.description()
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "    at '{}' (#{}), instruction {}", self.function_name, self.function_id, self.inst_index)
+    }
+}
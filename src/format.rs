@@ -0,0 +1,279 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use bytecode::Type;
+
+
+/// The largest width or precision a spec may have.
+pub const MAX_SPEC_NUMBER: usize = 0xFFFF;
+
+/// A parsed format template. Placeholders have the form '{type}' or
+/// '{type:spec}', where the type is one of the numeric type names such as
+/// 'i64', 'f32' or 'bigint'. The spec has the form
+///
+/// ```text
+/// [[fill]align][0][width][.precision][x|X]
+/// ```
+///
+/// with align being one of '<', '>' or '^'. Width and precision are at most
+/// MAX_SPEC_NUMBER. Braces are escaped as '{{' and '}}'.
+pub struct Template {
+    pub segments: Vec<Segment>,
+}
+
+pub enum Segment {
+    Literal(String),
+    Placeholder(Type, Spec),
+}
+
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+pub struct Spec {
+    pub fill: char,
+    pub align: Option<Align>,
+    pub zero: bool,
+    pub width: usize,
+    pub precision: Option<usize>,
+    pub hex: Option<Hex>,
+}
+
+pub enum Hex {
+    Lower,
+    Upper,
+}
+
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(literal));
+                        literal = String::new();
+                    }
+                    segments.push(try!(Template::parse_placeholder(&mut chars)));
+                },
+                '}' => return Err("Unmatched '}' in format template.".to_string()),
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments: segments })
+    }
+
+    pub fn placeholder_count(&self) -> usize {
+        self.segments.iter().filter(|segment| match **segment {
+            Segment::Placeholder(..) => true,
+            Segment::Literal(..) => false,
+        }).count()
+    }
+
+    /// Expects the opening brace to be consumed already.
+    fn parse_placeholder(chars: &mut Peekable<Chars>) -> Result<Segment, String> {
+        let mut content = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => content.push(c),
+                None => return Err("Unterminated placeholder in format template.".to_string()),
+            }
+        }
+
+        let (type_name, spec) = match content.find(':') {
+            Some(position) => (&content[..position], try!(Spec::parse(&content[position + 1..]))),
+            None => (&content[..], Spec::new()),
+        };
+
+        let t = match type_name {
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "bigint" => Type::BigInt,
+            _ => return Err(format!("Unknown placeholder type '{}'.", type_name)),
+        };
+
+        let is_float = t == Type::F32 || t == Type::F64;
+        if is_float && spec.hex.is_some() {
+            return Err(format!("Floats cannot be formatted as hex: '{{{}}}'.", content));
+        }
+        if !is_float && spec.precision.is_some() {
+            return Err(format!("Only floats have a precision: '{{{}}}'.", content));
+        }
+
+        Ok(Segment::Placeholder(t, spec))
+    }
+}
+
+impl Spec {
+    pub fn new() -> Spec {
+        Spec {
+            fill: ' ',
+            align: None,
+            zero: false,
+            width: 0,
+            precision: None,
+            hex: None,
+        }
+    }
+
+    fn parse(spec: &str) -> Result<Spec, String> {
+        let mut result = Spec::new();
+        let chars: Vec<char> = spec.chars().collect();
+        let mut i = 0;
+
+        // Fill and alignment.
+        if chars.len() >= 2 && Spec::parse_align(chars[1]).is_some() {
+            result.fill = chars[0];
+            result.align = Spec::parse_align(chars[1]);
+            i = 2;
+        } else if chars.len() >= 1 && Spec::parse_align(chars[0]).is_some() {
+            result.align = Spec::parse_align(chars[0]);
+            i = 1;
+        }
+
+        if i < chars.len() && chars[i] == '0' {
+            result.zero = true;
+            i += 1;
+        }
+
+        let (width, next) = try!(Spec::parse_number(&chars, i, spec));
+        result.width = width.unwrap_or(0);
+        i = next;
+
+        if i < chars.len() && chars[i] == '.' {
+            let (precision, next) = try!(Spec::parse_number(&chars, i + 1, spec));
+            if precision.is_none() {
+                return Err(format!("Expected a precision in format spec '{}'.", spec));
+            }
+            result.precision = precision;
+            i = next;
+        }
+
+        if i < chars.len() {
+            match chars[i] {
+                'x' => result.hex = Some(Hex::Lower),
+                'X' => result.hex = Some(Hex::Upper),
+                _ => return Err(format!("Invalid format spec '{}'.", spec)),
+            }
+            i += 1;
+        }
+
+        if i < chars.len() {
+            return Err(format!("Invalid format spec '{}'.", spec));
+        }
+
+        Ok(result)
+    }
+
+    fn parse_align(c: char) -> Option<Align> {
+        match c {
+            '<' => Some(Align::Left),
+            '>' => Some(Align::Right),
+            '^' => Some(Align::Center),
+            _ => None,
+        }
+    }
+
+    fn parse_number(chars: &[char], start: usize, spec: &str) -> Result<(Option<usize>, usize), String> {
+        let mut i = start;
+        let mut number = None;
+        while i < chars.len() {
+            match chars[i].to_digit(10) {
+                Some(digit) => {
+                    let next = number.unwrap_or(0usize).checked_mul(10)
+                        .and_then(|n| n.checked_add(digit as usize))
+                        .unwrap_or(usize::max_value());
+                    if next > MAX_SPEC_NUMBER {
+                        return Err(format!("Width or precision in format spec '{}' exceeds {}.",
+                            spec, MAX_SPEC_NUMBER));
+                    }
+                    number = Some(next);
+                },
+                None => break,
+            }
+            i += 1;
+        }
+        Ok((number, i))
+    }
+
+    /// Renders an integer, which may be a big integer.
+    pub fn render_integer<T>(&self, value: T) -> String
+            where T: fmt::Display + fmt::LowerHex + fmt::UpperHex {
+        let body = match self.hex {
+            Some(Hex::Lower) => format!("{:x}", value),
+            Some(Hex::Upper) => format!("{:X}", value),
+            None => format!("{}", value),
+        };
+        self.pad(body)
+    }
+
+    pub fn render_float<T>(&self, value: T) -> String where T: fmt::Display {
+        let body = match self.precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => format!("{}", value),
+        };
+        self.pad(body)
+    }
+
+    fn pad(&self, body: String) -> String {
+        let length = body.chars().count();
+        if length >= self.width {
+            return body;
+        }
+        let padding = self.width - length;
+
+        // Zero padding goes between the sign and the digits, unless an
+        // explicit alignment is requested.
+        if self.zero && self.align.is_none() {
+            let (sign, digits) = if body.starts_with('-') {
+                body.split_at(1)
+            } else {
+                body.split_at(0)
+            };
+            return format!("{}{}{}", sign, repeat('0', padding), digits);
+        }
+
+        let fill = self.fill;
+        match self.align {
+            Some(Align::Left) => format!("{}{}", body, repeat(fill, padding)),
+            Some(Align::Center) => {
+                let left = padding / 2;
+                format!("{}{}{}", repeat(fill, left), body, repeat(fill, padding - left))
+            },
+            Some(Align::Right) | None => format!("{}{}", repeat(fill, padding), body),
+        }
+    }
+}
+
+fn repeat(c: char, count: usize) -> String {
+    (0..count).map(|_| c).collect()
+}
@@ -0,0 +1,27 @@
+use bytecode::{OPCODE_COUNT, Opcode};
+
+
+/// The amount of fuel that the execution of each opcode consumes.
+#[derive(Clone)]
+pub struct FuelCosts {
+    costs: Vec<u64>,
+}
+
+
+impl FuelCosts {
+    /// Every instruction consumes one unit of fuel.
+    pub fn uniform() -> FuelCosts {
+        FuelCosts {
+            costs: vec![1; OPCODE_COUNT],
+        }
+    }
+
+    pub fn set_cost(&mut self, opcode: Opcode, cost: u64) {
+        self.costs[opcode as usize] = cost;
+    }
+
+    #[inline(always)]
+    pub fn cost(&self, opcode: Opcode) -> u64 {
+        self.costs[opcode as usize]
+    }
+}
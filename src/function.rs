@@ -1,19 +1,31 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, BufReader, Result};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::mem;
+use std::cmp;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use bytecode::*;
+use context::{RegisterCode, ThreadedCode, constant_slot_bits};
 use cst::ConstantTable;
 use environment::Environment;
+use error::VmError;
+use format::Template;
 use io;
 
 
 pub const INVALID_FUNCTION_ID: u32 = 0xFFFFFFFF;
 
+/// Function files start with the magic bytes and the format version.
+pub const FUNCTION_MAGIC: &'static [u8; 4] = b"LFUN";
+
+/// Version 2 added the header and the optional signature. Files of version
+/// 1 had no header and are not read any more.
+pub const FUNCTION_FORMAT_VERSION: u16 = 2;
+
 pub struct Function {
     /// The ID of the function in the current environment.
     pub id: u32,
@@ -23,9 +35,27 @@ pub struct Function {
 
     pub sizes: Sizes,
 
+    /// The types of the arguments and results, if declared. Required to run
+    /// the function with typed values.
+    pub signature: Option<Signature>,
+
     pub constant_table: Arc<ConstantTable>,
 
     pub instructions: Instructions,
+
+    /// The pre-decoded instructions, translated when the bytecode is fetched
+    /// from the environment. Has to be reset when the instructions change.
+    pub threaded_code: Option<ThreadedCode>,
+
+    /// Translated together with the threaded code.
+    pub register_code: Option<RegisterCode>,
+
+    /// The bytecode fingerprint, computed together with the threaded code.
+    pub fingerprint: Option<u64>,
+
+    /// The parsed templates of 'printfmt' instructions by constant index,
+    /// parsed when constants are resolved.
+    pub templates: HashMap<ConstantTableIndex, Template>,
 }
 
 pub struct Sizes {
@@ -43,6 +73,11 @@ pub struct Sizes {
     pub max_operands: u16,
 }
 
+pub struct Signature {
+    pub arguments: Vec<Type>,
+    pub returns: Vec<Type>,
+}
+
 pub enum Instructions {
     File {
         path: PathBuf,
@@ -60,42 +95,80 @@ impl Function {
             id: INVALID_FUNCTION_ID,
             name: name,
             sizes: sizes,
+            signature: None,
             constant_table: constant_table,
             instructions: instructions,
+            threaded_code: None,
+            register_code: None,
+            fingerprint: None,
+            templates: HashMap::new(),
         }
     }
 
+    /// Reads the header of the function and fetches its constant table. The
+    /// instructions are only read when the function is fetched.
     // TODO Split into from_file and from_read.
-    pub fn from_file(environment: &mut Environment, path: &Path) -> Result<Function> {
-        let mut read = try!(Function::open_reader(path));
+    pub fn from_file(environment: &mut Environment, path: &Path) -> ::std::result::Result<Function, VmError> {
+        let load = |error: ::std::io::Error| VmError::LoadFailed {
+            name: path.display().to_string(),
+            message: format!("Could not read function: {}", error),
+        };
+
+        let mut read = try!(Function::open_reader(path).map_err(&load));
+
+        // Read magic bytes and format version.
+        let mut magic = [0; 4];
+        try!(read.read_exact(&mut magic).map_err(&load));
+        let version = try!(read.read_u16::<BigEndian>().map_err(&load));
+        if &magic != FUNCTION_MAGIC || version != FUNCTION_FORMAT_VERSION {
+            return Err(VmError::LoadFailed {
+                name: path.display().to_string(),
+                message: "Unknown function format or format version.".to_string(),
+            });
+        }
 
         // Read name.
-        let name = try!(io::read_string(&mut read));
+        let name = try!(io::read_string(&mut read).map_err(&load));
 
         // Read sizes.
-        let sizes = try!(Sizes::from_read(&mut read));
+        let sizes = try!(Sizes::from_read(&mut read).map_err(&load));
 
         // Read constant table name.
-        let constant_table_name = try!(io::read_string(&mut read));
+        let constant_table_name = try!(io::read_string(&mut read).map_err(&load));
+
+        // Read the signature, if declared.
+        let signature = try!(Signature::read_section(&mut read).map_err(&load));
+        if signature.as_ref().map_or(false, |signature| !signature.fits(&sizes)) {
+            return Err(VmError::LoadFailed {
+                name: path.display().to_string(),
+                message: "The signature does not fit the sizes of the function.".to_string(),
+            });
+        }
 
         // Calculate offset in file.
-        let file_offset = Function::calculate_instructions_offset(&name[..], &constant_table_name[..]);
+        let file_offset = Function::calculate_instructions_offset(&name[..], &constant_table_name[..],
+                                                                  signature.as_ref());
 
         // Fetch constant table.
         let mut cst_path = PathBuf::from(path.parent().unwrap_or(Path::new("")));
         cst_path.push(constant_table_name);
-        let constant_table = environment.fetch_constant_table(cst_path.as_path());
+        let constant_table = try!(environment.fetch_constant_table(cst_path.as_path()));
 
         Ok(
             Function {
                 id: INVALID_FUNCTION_ID,
                 name: name,
                 sizes: sizes,
+                signature: signature,
                 constant_table: constant_table,
                 instructions: Instructions::File {
                     path: PathBuf::from(path),
                     offset: file_offset,
                 },
+                threaded_code: None,
+                register_code: None,
+                fingerprint: None,
+                templates: HashMap::new(),
             }
         )
     }
@@ -107,14 +180,208 @@ impl Function {
         Ok(BufReader::new(file))
     }
 
-    pub fn calculate_instructions_offset(name: &str, constant_table_name: &str) -> u64 {
-        let mut offset = io::string_disk_size(name);
+    /// Returns 0 if the bytecode is not loaded.
+    pub fn instruction_count(&self) -> usize {
+        match self.instructions {
+            Instructions::Bytecode(ref vec) => vec.len(),
+            Instructions::File {..} => 0,
+        }
+    }
+
+    /// Replaces each Cst of a constant that fits into a stack slot with its
+    /// raw bits, so that executing it does not need the constant table, and
+    /// parses the templates of 'printfmt' instructions. Fails if an
+    /// instruction refers to a missing constant or to a constant of the wrong
+    /// kind, such as a Cst of a string, or if a template is malformed or does
+    /// not fit the arguments, in which case the function is left unchanged.
+    /// Does nothing if the bytecode is not loaded.
+    pub fn resolve_constants(&mut self) -> ::std::result::Result<(), VmError> {
+        let (resolved, templates) = {
+            let insts = match self.instructions {
+                Instructions::Bytecode(ref vec) => vec,
+                Instructions::File {..} => return Ok(()),
+            };
+            try!(self.resolve_instructions(insts))
+        };
+
+        self.instructions = Instructions::Bytecode(resolved);
+        self.templates = templates;
+        Ok(())
+    }
+
+    fn resolve_instructions(&self, insts: &[Instruction])
+            -> ::std::result::Result<(Vec<Instruction>, HashMap<ConstantTableIndex, Template>), VmError> {
+        let table = &self.constant_table.table;
+        let mut resolved = Vec::with_capacity(insts.len());
+        let mut templates = HashMap::new();
+
+        for inst in insts {
+            // Whether the instruction needs a string or a number.
+            let (index, string) = match *inst {
+                Instruction::Cst(index) => (index, false),
+                Instruction::PrintFmt(index, _) |
+                Instruction::Assert(index) |
+                Instruction::Abort(index) => (index, true),
+                _ => {
+                    resolved.push(*inst);
+                    continue;
+                },
+            };
+
+            let constant = match table.get(index as usize) {
+                Some(constant) => constant,
+                None => return Err(VmError::InvalidConstant { index: index, expected: "an existing constant" }),
+            };
+
+            match (constant, string) {
+                (&Constant::Str(ref template), true) => {
+                    if let Instruction::PrintFmt(_, argc) = *inst {
+                        let parsed = try!(parse_template(template, argc));
+                        templates.insert(index, parsed);
+                    }
+                },
+                (_, true) => return Err(VmError::InvalidConstant { index: index, expected: "a string" }),
+                (&Constant::Str(..), false) | (&Constant::Bytes(..), false) => {
+                    return Err(VmError::InvalidConstant { index: index, expected: "a number" });
+                },
+                _ => { },
+            }
+
+            match (*inst, constant_slot_bits(constant)) {
+                (Instruction::Cst(..), Some(bits)) => resolved.push(Instruction::CstBits(index, bits)),
+                _ => resolved.push(*inst),
+            }
+        }
+
+        Ok((resolved, templates))
+    }
+
+    /// A hash of the sizes, the instructions and the constant table, which
+    /// changes with the bytecode. Resolving and specializing instructions on
+    /// fetch does not change it. Returns None if the bytecode is not loaded.
+    pub fn bytecode_fingerprint(&self) -> Option<u64> {
+        let insts = match self.instructions {
+            Instructions::Bytecode(ref vec) => vec,
+            Instructions::File {..} => return None,
+        };
+
+        // The function is encoded like in its file, with instructions in
+        // their generic form.
+        let mut bytes = Vec::new();
+        bytes.write_u8(self.sizes.return_count).unwrap();
+        bytes.write_u8(self.sizes.argument_count).unwrap();
+        bytes.write_u16::<BigEndian>(self.sizes.locals_count).unwrap();
+        bytes.write_u16::<BigEndian>(self.sizes.max_operands).unwrap();
+        bytes.write_u32::<BigEndian>(insts.len() as u32).unwrap();
+        for inst in insts {
+            inst.write(&mut bytes).unwrap();
+        }
+        bytes.extend_from_slice(&self.constant_table.to_bytes()[..]);
+
+        // FNV-1a, which is stable across processes and platforms.
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Some(hash)
+    }
+
+    /// Lists the instructions within radius of inst_index, one per line,
+    /// marking the instruction at inst_index.
+    pub fn disassemble_around(&self, inst_index: usize, radius: usize) -> String {
+        let insts = match self.instructions {
+            Instructions::Bytecode(ref vec) => vec,
+            Instructions::File {..} => return "        <bytecode not loaded>\n".to_string(),
+        };
+
+        let first = if inst_index > radius { inst_index - radius } else { 0 };
+        let last = cmp::min(inst_index + radius + 1, insts.len());
+        let mut output = String::new();
+        for index in first..last {
+            let marker = if index == inst_index { "->" } else { "  " };
+            output.push_str(&format!("     {} {:4}: {:?}\n", marker, index, insts[index]));
+        }
+        output
+    }
+
+    pub fn calculate_sizes_offset(name: &str) -> u64 {
+        let offset = FUNCTION_MAGIC.len() + mem::size_of::<u16>() + io::string_disk_size(name);
+        offset as u64
+    }
+
+    pub fn calculate_instructions_offset(name: &str, constant_table_name: &str,
+                                         signature: Option<&Signature>) -> u64 {
+        let mut offset = Function::calculate_sizes_offset(name) as usize;
         offset += Sizes::disk_size();
         offset += io::string_disk_size(constant_table_name);
+        offset += Signature::section_disk_size(signature);
         offset as u64
     }
 }
 
+/// Parses the template of a 'printfmt' with argc arguments, which must have
+/// as many placeholders.
+pub fn parse_template(template: &str, argc: u8) -> ::std::result::Result<Template, VmError> {
+    let template = match Template::parse(template) {
+        Ok(template) => template,
+        Err(message) => return Err(VmError::InvalidFormat(message)),
+    };
+
+    if template.placeholder_count() != argc as usize {
+        return Err(VmError::InvalidFormat(format!(
+            "The format template expects {} arguments but got {}.",
+            template.placeholder_count(), argc)));
+    }
+    Ok(template)
+}
+
+/// Reads a list of types preceded by its u8 length.
+fn read_types(read: &mut Read) -> Result<Vec<Type>> {
+    let count = try!(read.read_u8());
+    let mut types = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        types.push(try!(Type::from_read(read)));
+    }
+    Ok(types)
+}
+
+impl Signature {
+    pub fn new(arguments: Vec<Type>, returns: Vec<Type>) -> Signature {
+        Signature {
+            arguments: arguments,
+            returns: returns,
+        }
+    }
+
+    /// Whether the signature declares as many arguments and results as the
+    /// sizes of the function.
+    pub fn fits(&self, sizes: &Sizes) -> bool {
+        self.arguments.len() == sizes.argument_count as usize
+            && self.returns.len() == sizes.return_count as usize
+    }
+
+    /// Reads the signature section of a function file, which starts with a
+    /// byte that tells whether a signature follows. The argument and result
+    /// types are each preceded by their count.
+    pub fn read_section(read: &mut Read) -> Result<Option<Signature>> {
+        if try!(read.read_u8()) == 0 {
+            return Ok(None);
+        }
+
+        let arguments = try!(read_types(read));
+        let returns = try!(read_types(read));
+        Ok(Some(Signature::new(arguments, returns)))
+    }
+
+    pub fn section_disk_size(signature: Option<&Signature>) -> usize {
+        match signature {
+            Some(signature) => 3 + signature.arguments.len() + signature.returns.len(),
+            None => 1,
+        }
+    }
+}
+
 impl Sizes {
     pub fn new(return_count: u8, argument_count: u8,
             locals_count: u16, max_operands: u16) -> Sizes {
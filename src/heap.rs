@@ -0,0 +1,97 @@
+use std::mem;
+
+use num::BigInt;
+
+use error::VmError;
+
+
+/// Objects that do not fit into a stack slot. A stack slot refers to a heap
+/// object by its handle, which is only valid until the heap is cleared.
+///
+/// Objects are never reclaimed while a function runs, even once no slot
+/// refers to them, so every allocation grows the heap until the next run
+/// clears it. Set a limit to bound the growth.
+pub struct Heap {
+    big_ints: Vec<BigInt>,
+
+    /// The approximate amount of bytes that the objects occupy.
+    bytes: usize,
+
+    /// The amount of bytes the objects may occupy, if limited.
+    limit: Option<usize>,
+}
+
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            big_ints: Vec::new(),
+            bytes: 0,
+            limit: None,
+        }
+    }
+
+    /// Fails with VmError::OutOfMemory if the big integer would exceed the limit.
+    pub fn allocate_big_int(&mut self, value: BigInt) -> Result<u64, VmError> {
+        let size = big_int_size(&value);
+        try!(self.reserve(size));
+        let handle = self.big_ints.len() as u64;
+        self.big_ints.push(value);
+        self.bytes += size;
+        Ok(handle)
+    }
+
+    pub fn big_int(&self, handle: u64) -> Option<&BigInt> {
+        self.big_ints.get(handle as usize)
+    }
+
+    /// All big integers, indexed by their handles.
+    pub fn big_ints(&self) -> &[BigInt] {
+        &self.big_ints[..]
+    }
+
+    /// Replaces all objects, for example when a snapshot is restored. Fails
+    /// and keeps the current objects if they would exceed the limit.
+    pub fn restore(&mut self, big_ints: Vec<BigInt>) -> Result<(), VmError> {
+        let size = big_ints.iter().map(big_int_size).sum();
+        if let Some(limit) = self.limit {
+            if size > limit {
+                return Err(VmError::OutOfMemory { requested: size, available: limit });
+            }
+        }
+        self.big_ints = big_ints;
+        self.bytes = size;
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Invalidates all handles.
+    pub fn clear(&mut self) {
+        self.big_ints.clear();
+        self.bytes = 0;
+    }
+
+    fn reserve(&self, size: usize) -> Result<(), VmError> {
+        if let Some(limit) = self.limit {
+            if self.bytes + size > limit {
+                return Err(VmError::OutOfMemory {
+                    requested: size,
+                    available: limit.saturating_sub(self.bytes),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The handle table entry and the digits of the big integer.
+fn big_int_size(value: &BigInt) -> usize {
+    mem::size_of::<BigInt>() + (value.bits() as usize + 7) / 8
+}
@@ -0,0 +1,152 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, BufReader, Result};
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use io;
+
+
+/// A nominal type with its direct supertypes.
+#[derive(Clone)]
+pub struct NamedType {
+    /// The ID of the type in the current hierarchy.
+    pub id: u32,
+
+    /// The unique name of the type.
+    pub name: String,
+
+    /// The IDs of the direct supertypes.
+    pub supertypes: Vec<u32>,
+}
+
+#[derive(Clone)]
+pub struct TypeHierarchy {
+    types: Vec<NamedType>,
+    type_names_to_ids: HashMap<String, u32>,
+}
+
+/// A type declaration as it is stored on disk. Supertypes are referenced
+/// by name, since IDs are only assigned when the type is registered.
+pub struct TypeDeclaration {
+    pub name: String,
+    pub supertype_names: Vec<String>,
+}
+
+
+impl TypeHierarchy {
+
+    pub fn new() -> TypeHierarchy {
+        TypeHierarchy {
+            types: Vec::new(),
+            type_names_to_ids: HashMap::new(),
+        }
+    }
+
+    /// Registers a type with the given supertypes, which must already be registered.
+    pub fn register_type(&mut self, name: &str, supertypes: &[u32]) -> u32 {
+        if self.type_names_to_ids.contains_key(name) {
+            panic!("Type '{}' is already registered.", name);
+        }
+
+        for supertype in supertypes {
+            if *supertype as usize >= self.types.len() {
+                panic!("Supertype {} of type '{}' is not registered.", supertype, name);
+            }
+        }
+
+        let next_id: u32 = self.types.len() as u32;
+        self.types.push(NamedType {
+            id: next_id,
+            name: name.to_string(),
+            supertypes: supertypes.to_vec(),
+        });
+        self.type_names_to_ids.insert(name.to_string(), next_id);
+
+        next_id
+    }
+
+    /// Does not check whether the type exists.
+    pub fn get_type_by_id(&self, id: u32) -> &NamedType {
+        &self.types[id as usize]
+    }
+
+    pub fn get_type_by_name(&self, name: &str) -> Option<&NamedType> {
+        match self.type_names_to_ids.get(name) {
+            Some(id) => Some(self.get_type_by_id(*id)),
+            None => None,
+        }
+    }
+
+    /// Whether `subtype <: supertype`. Every type is a subtype of itself.
+    /// Unknown IDs are never subtypes of anything.
+    pub fn is_subtype(&self, subtype: u32, supertype: u32) -> bool {
+        if subtype as usize >= self.types.len() || supertype as usize >= self.types.len() {
+            return false;
+        }
+
+        // Supertypes are always registered before their subtypes, so we can
+        // stop searching as soon as an ID is smaller than the target. Each
+        // type is visited once, since diamonds reach it on many paths.
+        let mut pending = vec![subtype];
+        let mut visited = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if id == supertype {
+                return true;
+            }
+
+            for parent in &self.types[id as usize].supertypes {
+                if *parent >= supertype && visited.insert(*parent) {
+                    pending.push(*parent);
+                }
+            }
+        }
+
+        false
+    }
+
+}
+
+impl TypeDeclaration {
+    pub fn new(name: String, supertype_names: Vec<String>) -> TypeDeclaration {
+        TypeDeclaration {
+            name: name,
+            supertype_names: supertype_names,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Vec<TypeDeclaration>> {
+        let mut with_extension = PathBuf::from(path);
+        with_extension.set_extension("types");
+
+        let file = try!(File::open(with_extension.as_path()));
+        let mut read = BufReader::new(file);
+
+        TypeDeclaration::all_from_read(&mut read)
+    }
+
+    pub fn all_from_read(read: &mut Read) -> Result<Vec<TypeDeclaration>> {
+        let count = try!(read.read_u16::<BigEndian>());
+        let mut declarations = Vec::with_capacity(count as usize);
+
+        for _ in 0..count {
+            let declaration = try!(TypeDeclaration::from_read(read));
+            declarations.push(declaration);
+        }
+
+        Ok(declarations)
+    }
+
+    pub fn from_read(read: &mut Read) -> Result<TypeDeclaration> {
+        let name = try!(io::read_string(read));
+
+        let supertype_count = try!(read.read_u8());
+        let mut supertype_names = Vec::with_capacity(supertype_count as usize);
+        for _ in 0..supertype_count {
+            supertype_names.push(try!(io::read_string(read)));
+        }
+
+        Ok(TypeDeclaration::new(name, supertype_names))
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+/// Lets other threads interrupt the execution of a Context. The interpreter
+/// checks for interrupts at safe points, which are calls, resumptions and the
+/// boundaries between instructions. Register code and compiled functions are
+/// straight-line code that runs to its end, so they are only checked when
+/// they are called. Backward branches will be safe points once the VM has
+/// them.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle {
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Requests that execution stops at the next safe point.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// Withdraws a pending interrupt. The interpreter does this itself when
+    /// it stops at a safe point.
+    pub fn clear(&self) {
+        self.interrupted.store(false, Ordering::Relaxed);
+    }
+}
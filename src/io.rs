@@ -1,4 +1,4 @@
-use std::io::{Read, Write, Result};
+use std::io::{Error, ErrorKind, Read, Write, Result};
 use std::mem;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
@@ -6,18 +6,10 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 pub fn read_string(read: &mut Read) -> Result<String> {
     let string_length = try!(read.read_u16::<BigEndian>()) as usize;
-    let mut string_bytes = Vec::with_capacity(string_length);
-    unsafe {
-        string_bytes.set_len(string_length);
-    }
-
-    let result = try!(read.read(&mut string_bytes[..]));
-    if result != string_length {
-        panic!("Read length {} does not equal the string length {}.",
-                result, string_length);
-    }
+    let mut string_bytes = vec![0; string_length];
+    try!(read.read_exact(&mut string_bytes[..]));
 
-    Ok(String::from_utf8(string_bytes).unwrap())
+    String::from_utf8(string_bytes).map_err(|error| Error::new(ErrorKind::InvalidData, error))
 }
 
 pub fn write_string(write: &mut Write, string: &str) -> Result<()> {
@@ -26,6 +18,25 @@ pub fn write_string(write: &mut Write, string: &str) -> Result<()> {
     Ok(())
 }
 
+/// Reads bytes prefixed with a u32 length. The bytes are read as they
+/// arrive, so a corrupt length fails at the end of the input instead of
+/// allocating up to 4 GiB first.
+pub fn read_blob(read: &mut Read) -> Result<Vec<u8>> {
+    let length = try!(read.read_u32::<BigEndian>()) as u64;
+    let mut bytes = Vec::new();
+    try!((&mut *read).take(length).read_to_end(&mut bytes));
+    if bytes.len() as u64 != length {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "A blob is shorter than its length."));
+    }
+    Ok(bytes)
+}
+
+pub fn write_blob(write: &mut Write, bytes: &[u8]) -> Result<()> {
+    try!(write.write_u32::<BigEndian>(bytes.len() as u32));
+    try!(write.write_all(bytes));
+    Ok(())
+}
+
 pub fn string_disk_size(string: &str) -> usize {
     return mem::size_of::<u16>() + string.len();
 }
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::mem;
+use std::os::raw::{c_int, c_long, c_void};
+use std::ptr;
+
+use bytecode::{Instruction, Type};
+use function::{Function, Instructions};
+
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const PROT_EXEC: c_int = 0x4;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+
+/// Executable memory is mapped in whole pages.
+const PAGE_SIZE: usize = 4096;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, length: usize, prot: c_int, flags: c_int,
+            fd: c_int, offset: c_long) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, length: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, length: usize) -> c_int;
+}
+
+/// Receives a pointer to the bottom of the frame and a pointer to where the
+/// results go. Returns the index of the instruction at which the interpreter
+/// continues in the low half, and the operand count in the high half.
+type Entry = extern "C" fn(*mut u64, *mut u64) -> u64;
+
+/// Compiles functions into x86-64 machine code once they have been called
+/// often enough. Functions are identified by their IDs and bytecode
+/// fingerprints, which cover the sizes, so a function only ever runs code
+/// compiled from the same bytecode. Each ID has a single entry, which starts
+/// over when a function with another fingerprint is entered under the ID, so
+/// that the code of replaced functions is freed.
+pub struct Jit {
+    /// The number of calls after which a function is compiled.
+    threshold: u32,
+
+    functions: HashMap<u32, JitEntry>,
+
+    /// The executable memory of all compiled functions, in bytes.
+    code_size: usize,
+}
+
+struct JitEntry {
+    /// The bytecode fingerprint of the function that the state belongs to.
+    fingerprint: u64,
+
+    state: JitState,
+}
+
+enum JitState {
+    Counting(u32),
+    Compiled(NativeCode),
+
+    /// The function uses instructions that the JIT does not support.
+    Unsupported,
+}
+
+/// Machine code in executable memory. It only reads and writes the stack
+/// elements of its frame and the results, which the caller has checked to
+/// be in bounds.
+pub struct NativeCode {
+    memory: *mut u8,
+    length: usize,
+    entry: Entry,
+
+    /// The bytecode fingerprint of the function the code was compiled from.
+    fingerprint: u64,
+
+    /// The locals, maximum operand and return counts of the function the
+    /// code was compiled from, which bound the elements the code accesses.
+    sizes: (u16, u16, u8),
+}
+
+// The memory is immutable once the code has been created.
+unsafe impl Send for NativeCode { }
+unsafe impl Sync for NativeCode { }
+
+
+impl Jit {
+    pub fn new(threshold: u32) -> Jit {
+        Jit {
+            threshold: threshold,
+            functions: HashMap::new(),
+            code_size: 0,
+        }
+    }
+
+    /// Counts a call of the function. Returns its native code if it is, or
+    /// has just become, hot enough to be compiled. Functions that have not
+    /// been fetched from an environment are never compiled. Code that takes
+    /// more bytes than the budget is discarded, and the function counts its
+    /// calls anew.
+    pub fn enter(&mut self, function: &Function, budget: Option<usize>) -> Option<&NativeCode> {
+        let fingerprint = match function.fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => return None,
+        };
+
+        let threshold = self.threshold;
+        let code_size = &mut self.code_size;
+        let entry = self.functions.entry(function.id).or_insert(JitEntry {
+            fingerprint: fingerprint,
+            state: JitState::Counting(0),
+        });
+
+        // Another function has taken the place of the one the state belongs to.
+        if entry.fingerprint != fingerprint {
+            entry.fingerprint = fingerprint;
+            if let JitState::Compiled(ref code) = entry.state {
+                *code_size -= code.size();
+            }
+            entry.state = JitState::Counting(0);
+        }
+        let state = &mut entry.state;
+
+        let calls = match *state {
+            JitState::Counting(calls) => calls + 1,
+            _ => 0,
+        };
+        if calls > 0 {
+            *state = if calls >= threshold {
+                match NativeCode::compile(function) {
+                    Some(ref code) if budget.map_or(false, |budget| code.size() > budget) => {
+                        JitState::Counting(0)
+                    },
+                    Some(code) => {
+                        *code_size += code.size();
+                        JitState::Compiled(code)
+                    },
+                    None => JitState::Unsupported,
+                }
+            } else {
+                JitState::Counting(calls)
+            };
+        }
+
+        match *state {
+            JitState::Compiled(ref code) if code.fits(function, fingerprint) => Some(code),
+            _ => None,
+        }
+    }
+
+    pub fn code_size(&self) -> usize {
+        self.code_size
+    }
+
+    pub fn is_compiled(&self, function: &Function) -> bool {
+        match (self.functions.get(&function.id), function.fingerprint) {
+            (Some(&JitEntry { state: JitState::Compiled(ref code), .. }), Some(fingerprint)) => {
+                code.fits(function, fingerprint)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl NativeCode {
+    /// Returns None if the function uses instructions that cannot be compiled
+    /// yet, which currently are all but stack manipulation, resolved
+    /// constants, returns, and arithmetic and comparisons of 32- and 64-bit
+    /// integers and floats apart from float remainders. Integer divisions
+    /// exit to the interpreter when they would trap or overflow. The code
+    /// only accesses the locals, operands and results that the sizes of the
+    /// function provide.
+    pub fn compile(function: &Function) -> Option<NativeCode> {
+        let insts = match function.instructions {
+            Instructions::Bytecode(ref vec) => vec,
+            Instructions::File {..} => return None,
+        };
+        let fingerprint = match function.fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => return None,
+        };
+
+        let locals_count = function.sizes.locals_count as usize;
+        let max_operands = function.sizes.max_operands as usize;
+        let mut assembler = Assembler { code: Vec::new() };
+
+        // The operand stack depth is known at every instruction.
+        let mut depth: usize = 0;
+        for (index, inst) in insts.iter().enumerate() {
+            let (pops, pushes) = inst.stack_effect();
+            if pops > depth || depth - pops + pushes > max_operands {
+                return None;
+            }
+            let top = locals_count + depth;
+
+            match *inst {
+                Instruction::Nop => { },
+                Instruction::Pop => depth -= 1,
+                Instruction::Dup => {
+                    assembler.load_rax(top - 1);
+                    assembler.store_rax(top);
+                    depth += 1;
+                },
+                Instruction::Load(var) if (var as usize) < locals_count => {
+                    assembler.load_rax(var as usize);
+                    assembler.store_rax(top);
+                    depth += 1;
+                },
+                Instruction::Store(var) if (var as usize) < locals_count => {
+                    assembler.load_rax(top - 1);
+                    assembler.store_rax(var as usize);
+                    depth -= 1;
+                },
+                Instruction::CstBits(_, bits) => {
+                    assembler.mov_rax_imm(bits);
+                    assembler.store_rax(top);
+                    depth += 1;
+                },
+                Instruction::Ret(count) => {
+                    let count = count as usize;
+                    if count > depth || count > function.sizes.return_count as usize {
+                        return None;
+                    }
+                    for i in 0..count {
+                        assembler.load_rax(top - count + i);
+                        assembler.store_rax_result(i);
+                    }
+                },
+                _ => {
+                    let generic = inst.generic();
+                    let index = index as u64;
+                    if !assembler.arithmetic(&generic, top, index, depth as u64) {
+                        return None;
+                    }
+                    depth -= 1;
+                },
+            }
+        }
+
+        assembler.exit(insts.len() as u64, depth as u64);
+
+        let sizes = (function.sizes.locals_count, function.sizes.max_operands, function.sizes.return_count);
+        unsafe { NativeCode::from_machine_code(&assembler.code[..], fingerprint, sizes) }
+    }
+
+    /// The bytes of executable memory the code occupies.
+    pub fn size(&self) -> usize {
+        (self.length + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE
+    }
+
+    /// Whether the code has been compiled from a function with the sizes and
+    /// the fingerprint.
+    fn fits(&self, function: &Function, fingerprint: u64) -> bool {
+        let sizes = &function.sizes;
+        self.fingerprint == fingerprint &&
+            self.sizes == (sizes.locals_count, sizes.max_operands, sizes.return_count)
+    }
+
+    unsafe fn from_machine_code(code: &[u8], fingerprint: u64,
+                                sizes: (u16, u16, u8)) -> Option<NativeCode> {
+        let length = code.len();
+        let memory = mmap(ptr::null_mut(), length, PROT_READ | PROT_WRITE,
+                          MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+        if memory as isize == -1 {
+            return None;
+        }
+
+        ptr::copy_nonoverlapping(code.as_ptr(), memory as *mut u8, length);
+        if mprotect(memory, length, PROT_READ | PROT_EXEC) != 0 {
+            munmap(memory, length);
+            return None;
+        }
+
+        Some(NativeCode {
+            memory: memory as *mut u8,
+            length: length,
+            entry: mem::transmute(memory),
+            fingerprint: fingerprint,
+            sizes: sizes,
+        })
+    }
+
+    /// The frame needs the locals and maximum operand count of the function.
+    /// Returns the index of the instruction that has to be interpreted next,
+    /// which is the instruction count if the code ran to the end, and the
+    /// operand count at that instruction.
+    pub unsafe fn call(&self, frame_bottom: *mut u64, results: *mut u64) -> (usize, usize) {
+        let exit = (self.entry)(frame_bottom, results);
+        ((exit & 0xFFFF_FFFF) as usize, (exit >> 32) as usize)
+    }
+}
+
+impl Drop for NativeCode {
+    fn drop(&mut self) {
+        unsafe { munmap(self.memory as *mut c_void, self.length) };
+    }
+}
+
+/// Emits the few instructions the JIT needs. The frame bottom is in rdi
+/// and the results pointer in rsi.
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    fn emit_disp32(&mut self, element: usize) {
+        let displacement = (element * 8) as u32;
+        self.code.extend_from_slice(&[
+            displacement as u8,
+            (displacement >> 8) as u8,
+            (displacement >> 16) as u8,
+            (displacement >> 24) as u8,
+        ]);
+    }
+
+    /// mov rax, [rdi + element * 8]
+    fn load_rax(&mut self, element: usize) {
+        self.code.extend_from_slice(&[0x48, 0x8B, 0x87]);
+        self.emit_disp32(element);
+    }
+
+    /// mov rcx, [rdi + element * 8]
+    fn load_rcx(&mut self, element: usize) {
+        self.code.extend_from_slice(&[0x48, 0x8B, 0x8F]);
+        self.emit_disp32(element);
+    }
+
+    /// mov [rdi + element * 8], rax
+    fn store_rax(&mut self, element: usize) {
+        self.code.extend_from_slice(&[0x48, 0x89, 0x87]);
+        self.emit_disp32(element);
+    }
+
+    /// mov [rsi + index * 8], rax
+    fn store_rax_result(&mut self, index: usize) {
+        self.code.extend_from_slice(&[0x48, 0x89, 0x86]);
+        self.emit_disp32(index);
+    }
+
+    /// mov rax, value
+    fn mov_rax_imm(&mut self, value: u64) {
+        self.code.extend_from_slice(&[0x48, 0xB8]);
+        for i in 0..8 {
+            self.code.push((value >> (i * 8)) as u8);
+        }
+    }
+
+    /// Returns the instruction index and the operand count at which the
+    /// interpreter continues.
+    fn exit(&mut self, index: u64, depth: u64) {
+        self.mov_rax_imm(index | depth << 32);
+        self.code.push(0xC3);
+    }
+
+    /// Emits the test and a conditional jump over an exit, which is taken
+    /// if the jump is not.
+    fn exit_unless(&mut self, test: &[u8], jump: u8, index: u64, depth: u64) {
+        self.code.extend_from_slice(test);
+        // The exit is a 10 byte move and a ret.
+        self.code.extend_from_slice(&[jump, 11]);
+        self.exit(index, depth);
+    }
+
+    /// Emits a binary operation on the two topmost operands, which end below
+    /// top. Returns false if the instruction cannot be compiled. Operations
+    /// that would trap exit to the interpreter before the instruction, which
+    /// then reports the error.
+    fn arithmetic(&mut self, inst: &Instruction, top: usize, index: u64, depth: u64) -> bool {
+        let t = match *inst {
+            Instruction::Add(t) | Instruction::Sub(t) | Instruction::Mul(t) |
+            Instruction::Div(t) | Instruction::Rem(t) | Instruction::Cmp(t) => t,
+            _ => return false,
+        };
+        match t {
+            Type::U64 | Type::U32 | Type::I64 | Type::I32 => {
+                self.integer(inst, t, top, index, depth);
+                true
+            },
+            Type::F64 | Type::F32 => self.float(inst, t == Type::F64, top),
+            _ => false,
+        }
+    }
+
+    /// 32-bit operations only read the low halves of the operands, like the
+    /// interpreter, and extend their results according to the signedness.
+    fn integer(&mut self, inst: &Instruction, t: Type, top: usize, index: u64, depth: u64) {
+        let wide = t == Type::U64 || t == Type::I64;
+        let signed = t == Type::I64 || t == Type::I32;
+        let rex: &[u8] = if wide { &[0x48] } else { &[] };
+
+        self.load_rax(top - 2);
+        self.load_rcx(top - 1);
+        match *inst {
+            // Wrapping arithmetic is the same for signed and unsigned integers.
+            Instruction::Add(_) => {
+                // add eax, ecx
+                self.code.extend_from_slice(rex);
+                self.code.extend_from_slice(&[0x01, 0xC8]);
+            },
+            Instruction::Sub(_) => {
+                // sub eax, ecx
+                self.code.extend_from_slice(rex);
+                self.code.extend_from_slice(&[0x29, 0xC8]);
+            },
+            Instruction::Mul(_) => {
+                // imul eax, ecx
+                self.code.extend_from_slice(rex);
+                self.code.extend_from_slice(&[0x0F, 0xAF, 0xC1]);
+            },
+            Instruction::Div(_) | Instruction::Rem(_) => {
+                // test ecx, ecx; jnz
+                let mut test = rex.to_vec();
+                test.extend_from_slice(&[0x85, 0xC9]);
+                self.exit_unless(&test, 0x75, index, depth);
+                if signed {
+                    // The quotient of the minimum and -1 overflows, so
+                    // the interpreter wraps it.
+                    // cmp ecx, -1; jne
+                    let mut test = rex.to_vec();
+                    test.extend_from_slice(&[0x83, 0xF9, 0xFF]);
+                    self.exit_unless(&test, 0x75, index, depth);
+                    // cdq; idiv ecx
+                    self.code.extend_from_slice(rex);
+                    self.code.push(0x99);
+                    self.code.extend_from_slice(rex);
+                    self.code.extend_from_slice(&[0xF7, 0xF9]);
+                } else {
+                    // xor edx, edx; div ecx
+                    self.code.extend_from_slice(&[0x31, 0xD2]);
+                    self.code.extend_from_slice(rex);
+                    self.code.extend_from_slice(&[0xF7, 0xF1]);
+                }
+                if let Instruction::Rem(_) = *inst {
+                    // mov eax, edx
+                    self.code.extend_from_slice(rex);
+                    self.code.extend_from_slice(&[0x89, 0xD0]);
+                }
+            },
+            _ => {
+                // cmp eax, ecx
+                self.code.extend_from_slice(rex);
+                self.code.extend_from_slice(&[0x39, 0xC8]);
+                if signed {
+                    // setg al; setl cl
+                    self.code.extend_from_slice(&[0x0F, 0x9F, 0xC0, 0x0F, 0x9C, 0xC1]);
+                } else {
+                    // seta al; setb cl
+                    self.code.extend_from_slice(&[0x0F, 0x97, 0xC0, 0x0F, 0x92, 0xC1]);
+                }
+                self.comparison_result(top - 2);
+                return;
+            },
+        }
+
+        if signed && !wide {
+            // movsxd rax, eax
+            self.code.extend_from_slice(&[0x48, 0x63, 0xC0]);
+        }
+        self.store_rax(top - 2);
+    }
+
+    /// Uses SSE. F32 operands are read from the low halves of their
+    /// elements, and results clear the high halves.
+    fn float(&mut self, inst: &Instruction, double: bool, top: usize) -> bool {
+        let operation = match *inst {
+            Instruction::Add(_) => 0x58,
+            Instruction::Sub(_) => 0x5C,
+            Instruction::Mul(_) => 0x59,
+            Instruction::Div(_) => 0x5E,
+            Instruction::Cmp(_) => 0x2E,
+            _ => return false,
+        };
+
+        // movq xmm0, [rdi + a]; movq xmm1, [rdi + b]
+        // or movd for F32.
+        let load: &[u8] = if double { &[0xF3, 0x0F, 0x7E] } else { &[0x66, 0x0F, 0x6E] };
+        self.code.extend_from_slice(load);
+        self.code.push(0x87);
+        self.emit_disp32(top - 2);
+        self.code.extend_from_slice(load);
+        self.code.push(0x8F);
+        self.emit_disp32(top - 1);
+
+        if operation == 0x2E {
+            // ucomisd xmm1, xmm0 compares the operands the other way around,
+            // so that unordered operands set the carry flag like greater
+            // ones.
+            if double {
+                self.code.push(0x66);
+            }
+            self.code.extend_from_slice(&[0x0F, 0x2E, 0xC8]);
+            // setb al; seta cl
+            self.code.extend_from_slice(&[0x0F, 0x92, 0xC0, 0x0F, 0x97, 0xC1]);
+            self.comparison_result(top - 2);
+            return true;
+        }
+
+        // addsd xmm0, xmm1 or addss for F32, and so on.
+        self.code.extend_from_slice(&[if double { 0xF2 } else { 0xF3 }, 0x0F, operation, 0xC1]);
+        // movq [rdi + a], xmm0
+        self.code.extend_from_slice(&[0x66, 0x0F, 0xD6, 0x87]);
+        self.emit_disp32(top - 2);
+        true
+    }
+
+    /// Stores al - cl, which are flags for greater and less, as an i32.
+    fn comparison_result(&mut self, element: usize) {
+        // sub al, cl; movsx rax, al
+        self.code.extend_from_slice(&[0x28, 0xC8, 0x48, 0x0F, 0xBE, 0xC0]);
+        self.store_rax(element);
+    }
+}
@@ -10,7 +10,21 @@ extern crate num;
 pub mod bytecode;
 pub mod context;
 pub mod cst;
+pub mod debugger;
 pub mod environment;
+pub mod error;
+pub mod format;
+pub mod fuel;
 pub mod function;
+pub mod heap;
+pub mod hierarchy;
+pub mod interrupt;
 pub mod io;
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
+pub mod memory;
+pub mod observer;
+pub mod profiler;
 pub mod scribe;
+pub mod snapshot;
+pub mod value;
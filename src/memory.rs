@@ -0,0 +1,38 @@
+use std::fmt;
+
+
+/// The memory that a Context accounts for, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryUsage {
+    /// The allocated stack, including unused elements.
+    pub stack: usize,
+
+    /// The objects on the heap, approximately.
+    pub heap: usize,
+
+    /// The largest string that has been rendered since the last run started,
+    /// for example by 'printfmt'. Runtime strings only count against the
+    /// budget while they are alive.
+    pub strings: usize,
+
+    /// The executable memory of functions compiled by the JIT, in whole
+    /// pages.
+    pub code: usize,
+}
+
+
+impl MemoryUsage {
+    /// The memory in use, without the runtime strings, which are freed by
+    /// the instruction that creates them.
+    pub fn total(&self) -> usize {
+        self.stack + self.heap + self.code
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes of stack, {} bytes of heap, {} bytes of compiled code, \
+                   at most {} bytes of runtime strings",
+            self.stack, self.heap, self.code, self.strings)
+    }
+}
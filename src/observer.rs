@@ -0,0 +1,24 @@
+use bytecode::Instruction;
+use error::Fault;
+use function::Function;
+
+
+/// Receives execution events of a Context, for example to trace execution or
+/// to collect coverage. All callbacks do nothing by default. A Context only
+/// reports instructions while an observer is set, so that execution without
+/// one does not pay for it.
+pub trait ExecutionObserver {
+    /// Called before the instruction at inst_index executes.
+    fn on_instruction(&mut self, _function: &Function, _inst_index: usize, _inst: &Instruction) { }
+
+    /// Called when a frame for the function is pushed, before any of its
+    /// instructions execute.
+    fn on_call(&mut self, _function: &Function) { }
+
+    /// Called when the function has executed its last instruction.
+    fn on_return(&mut self, _function: &Function) { }
+
+    /// Called when an error stops the function. Suspensions, such as running
+    /// out of fuel, are not reported.
+    fn on_trap(&mut self, _function: &Function, _fault: &Fault) { }
+}
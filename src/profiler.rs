@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use num::FromPrimitive;
+
+use bytecode::{OPCODE_COUNT, Opcode};
+
+
+/// Collects execution counts and times while profiling is enabled on a Context.
+pub struct Profiler {
+    opcode_counts: Vec<u64>,
+
+    /// Keyed by function ID and instruction index.
+    instruction_counts: HashMap<(u32, usize), u64>,
+
+    /// Keyed by function ID.
+    functions: HashMap<u32, FunctionProfile>,
+}
+
+/// The results of profiling, ordered by descending counts and times. The
+/// fields can be consumed directly, and Display prints them as a table.
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    /// Opcodes that have not been executed are left out.
+    pub opcodes: Vec<(Opcode, u64)>,
+
+    pub instructions: Vec<InstructionProfile>,
+    pub functions: Vec<FunctionProfile>,
+}
+
+#[derive(Clone, Debug)]
+pub struct InstructionProfile {
+    pub function_id: u32,
+    pub inst_index: usize,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionProfile {
+    pub function_id: u32,
+    pub function_name: String,
+    pub calls: u64,
+
+    /// The time spent executing the function, including the time of the
+    /// calls that were made while it was active.
+    pub inclusive_time: Duration,
+
+    /// The time spent executing the instructions of the function itself.
+    pub exclusive_time: Duration,
+}
+
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            opcode_counts: vec![0; OPCODE_COUNT],
+            instruction_counts: HashMap::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn count_instruction(&mut self, function_id: u32, inst_index: usize, opcode: Opcode) {
+        self.opcode_counts[opcode as usize] += 1;
+        *self.instruction_counts.entry((function_id, inst_index)).or_insert(0) += 1;
+    }
+
+    pub fn count_call(&mut self, function_id: u32, function_name: &str) {
+        self.function(function_id, function_name).calls += 1;
+    }
+
+    /// Attributes time to the executing function. The time is inclusive for
+    /// all active functions, each of which must only be given once.
+    pub fn add_time(&mut self, function_id: u32, function_name: &str,
+                    active_function_ids: &[u32], time: Duration) {
+        self.function(function_id, function_name).exclusive_time += time;
+        for id in active_function_ids {
+            if let Some(profile) = self.functions.get_mut(id) {
+                profile.inclusive_time += time;
+            }
+        }
+    }
+
+    fn function(&mut self, function_id: u32, function_name: &str) -> &mut FunctionProfile {
+        self.functions.entry(function_id).or_insert_with(|| FunctionProfile {
+            function_id: function_id,
+            function_name: function_name.to_string(),
+            calls: 0,
+            inclusive_time: Duration::new(0, 0),
+            exclusive_time: Duration::new(0, 0),
+        })
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let mut opcodes: Vec<(Opcode, u64)> = self.opcode_counts.iter().enumerate()
+            .filter(|&(_, count)| *count > 0)
+            .map(|(opcode, count)| (Opcode::from_usize(opcode).unwrap(), *count))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let mut instructions: Vec<InstructionProfile> = self.instruction_counts.iter()
+            .map(|(&(function_id, inst_index), count)| InstructionProfile {
+                function_id: function_id,
+                inst_index: inst_index,
+                count: *count,
+            })
+            .collect();
+        instructions.sort_by(|a, b| (b.count, a.function_id, a.inst_index)
+                                .cmp(&(a.count, b.function_id, b.inst_index)));
+
+        let mut functions: Vec<FunctionProfile> = self.functions.values().cloned().collect();
+        functions.sort_by(|a, b| (b.inclusive_time, a.function_id)
+                             .cmp(&(a.inclusive_time, b.function_id)));
+
+        ProfileReport {
+            opcodes: opcodes,
+            instructions: instructions,
+            functions: functions,
+        }
+    }
+}
+
+impl ProfileReport {
+    /// How often instructions with the opcode have been executed.
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcodes.iter().find(|&&(o, _)| o == opcode).map_or(0, |&(_, count)| count)
+    }
+
+    /// How often the instruction at the index of the function has been executed.
+    pub fn instruction_count(&self, function_id: u32, inst_index: usize) -> u64 {
+        self.instructions.iter()
+            .find(|inst| inst.function_id == function_id && inst.inst_index == inst_index)
+            .map_or(0, |inst| inst.count)
+    }
+
+    pub fn function(&self, function_id: u32) -> Option<&FunctionProfile> {
+        self.functions.iter().find(|function| function.function_id == function_id)
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "Functions (calls, inclusive, exclusive):"));
+        for function in &self.functions {
+            try!(writeln!(f, "    '{}' (#{}): {}, {:?}, {:?}", function.function_name,
+                          function.function_id, function.calls,
+                          function.inclusive_time, function.exclusive_time));
+        }
+
+        try!(writeln!(f, "Opcodes:"));
+        for &(opcode, count) in &self.opcodes {
+            try!(writeln!(f, "    {:?}: {}", opcode, count));
+        }
+
+        try!(writeln!(f, "Instructions:"));
+        for inst in &self.instructions {
+            try!(writeln!(f, "    #{}, instruction {}: {}", inst.function_id, inst.inst_index, inst.count));
+        }
+        Ok(())
+    }
+}
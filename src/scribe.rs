@@ -5,8 +5,9 @@ use byteorder::{BigEndian, WriteBytesExt};
 
 use bytecode::*;
 use cst::ConstantTable;
+use hierarchy::TypeDeclaration;
 use io;
-use function::{Function, Sizes};
+use function::{FUNCTION_FORMAT_VERSION, FUNCTION_MAGIC, Function, Signature, Sizes};
 
 
 pub struct FunctionWriter<'a, W: 'a> where W: Write + Seek {
@@ -24,20 +25,41 @@ pub struct ConstantTableWriter<'a, W: 'a> where W: Write + Seek {
     write: &'a mut W,
 }
 
+pub struct TypeDeclarationWriter<'a, W: 'a> where W: Write + Seek {
+    write: &'a mut W,
+}
+
 
 impl<'a, W: Write + Seek> FunctionWriter<'a, W> {
 
     pub fn new(write: &'a mut W, name: &str, constant_table_name: &str, argument_count: u8) -> FunctionWriter<'a, W>
             where W: Write + Seek {
+        FunctionWriter::create(write, name, constant_table_name, argument_count, None)
+    }
+
+    /// Declares the signature in the file. The function has to return as
+    /// many results as the signature declares, or it fails to load.
+    pub fn with_signature(write: &'a mut W, name: &str, constant_table_name: &str,
+                          signature: &Signature) -> FunctionWriter<'a, W> {
+        let argument_count = signature.arguments.len() as u8;
+        FunctionWriter::create(write, name, constant_table_name, argument_count, Some(signature))
+    }
+
+    fn create(write: &'a mut W, name: &str, constant_table_name: &str, argument_count: u8,
+              signature: Option<&Signature>) -> FunctionWriter<'a, W> {
         let mut writer = FunctionWriter {
             write: write,
-            sizes_offset: io::string_disk_size(name) as u64,
-            instructions_offset: Function::calculate_instructions_offset(name, constant_table_name),
+            sizes_offset: Function::calculate_sizes_offset(name),
+            instructions_offset: Function::calculate_instructions_offset(name, constant_table_name, signature),
             instruction_count: 0,
             sizes: Sizes::new(0, argument_count, 0, 0),
             current_op_size: 0,
         };
 
+        // Write magic bytes and format version.
+        writer.write.write_all(FUNCTION_MAGIC).unwrap();
+        writer.write.write_u16::<BigEndian>(FUNCTION_FORMAT_VERSION).unwrap();
+
         // Write name.
         io::write_string(writer.write, name).unwrap();
 
@@ -50,6 +72,20 @@ impl<'a, W: Write + Seek> FunctionWriter<'a, W> {
         // Write constant table name.
         io::write_string(writer.write, constant_table_name).unwrap();
 
+        // Write the signature section.
+        match signature {
+            Some(signature) => {
+                writer.write.write_u8(1).unwrap();
+                for types in &[&signature.arguments, &signature.returns] {
+                    writer.write.write_u8(types.len() as u8).unwrap();
+                    for t in types.iter() {
+                        writer.write.write_u8(*t as u8).unwrap();
+                    }
+                }
+            },
+            None => writer.write.write_u8(0).unwrap(),
+        }
+
         // Reserve 4 bytes for the instruction count.
         writer.write.write_u32::<BigEndian>(0).unwrap();
 
@@ -89,7 +125,8 @@ impl<'a, W: Write + Seek> FunctionWriter<'a, W> {
 
     pub fn write_typed(&mut self, opcode: Opcode, t: Type) {
         match opcode {
-            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div => {
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div |
+            Opcode::Rem | Opcode::Cmp => {
                 // These instructions pop 2 elements from the stack,
                 // then push 1 element to the stack.
                 self.sizes_pop_operands(2);
@@ -106,6 +143,36 @@ impl<'a, W: Write + Seek> FunctionWriter<'a, W> {
         self.instruction_count += 1;
     }
 
+    pub fn write_conv(&mut self, from: Type, to: Type) {
+        self.sizes_pop_operands(1);
+        self.sizes_push_operands(1);
+        self.write.write_u8(Opcode::Conv as u8).unwrap();
+        self.write.write_u8(from as u8).unwrap();
+        self.write.write_u8(to as u8).unwrap();
+        self.instruction_count += 1;
+    }
+
+    pub fn write_print_fmt(&mut self, index: ConstantTableIndex, argc: u8) {
+        self.sizes_pop_operands(argc as u16);
+        self.write.write_u8(Opcode::PrintFmt as u8).unwrap();
+        self.write.write_u16::<BigEndian>(index).unwrap();
+        self.write.write_u8(argc).unwrap();
+        self.instruction_count += 1;
+    }
+
+    pub fn write_assert(&mut self, index: ConstantTableIndex) {
+        self.sizes_pop_operands(1);
+        self.write.write_u8(Opcode::Assert as u8).unwrap();
+        self.write.write_u16::<BigEndian>(index).unwrap();
+        self.instruction_count += 1;
+    }
+
+    pub fn write_abort(&mut self, index: ConstantTableIndex) {
+        self.write.write_u8(Opcode::Abort as u8).unwrap();
+        self.write.write_u16::<BigEndian>(index).unwrap();
+        self.instruction_count += 1;
+    }
+
     pub fn write_cst(&mut self, index: ConstantTableIndex) {
         self.sizes_push_operands(1);
         self.write.write_u8(Opcode::Cst as u8).unwrap();
@@ -140,6 +207,22 @@ impl<'a, W: Write + Seek> FunctionWriter<'a, W> {
         self.instruction_count += 1;
     }
 
+    pub fn write_is_instance(&mut self, id: NamedTypeId) {
+        self.sizes_pop_operands(1);
+        self.sizes_push_operands(1);
+        self.write.write_u8(Opcode::IsInstance as u8).unwrap();
+        self.write.write_u32::<BigEndian>(id).unwrap();
+        self.instruction_count += 1;
+    }
+
+    pub fn write_cast(&mut self, id: NamedTypeId) {
+        self.sizes_pop_operands(1);
+        self.sizes_push_operands(1);
+        self.write.write_u8(Opcode::Cast as u8).unwrap();
+        self.write.write_u32::<BigEndian>(id).unwrap();
+        self.instruction_count += 1;
+    }
+
     fn sizes_used_var(&mut self, var: VariableIndex) {
         self.sizes.locals_count = cmp::max(self.sizes.locals_count, var + 1);
     }
@@ -206,6 +289,22 @@ impl<'a, W: Write + Seek> ConstantTableWriter<'a, W> {
                 self.write.write_u8(ConstantTag::I32 as u8).unwrap();
                 self.write.write_i32::<BigEndian>(num).unwrap();
             },
+            Constant::U16(num) => {
+                self.write.write_u8(ConstantTag::U16 as u8).unwrap();
+                self.write.write_u16::<BigEndian>(num).unwrap();
+            },
+            Constant::U8(num) => {
+                self.write.write_u8(ConstantTag::U8 as u8).unwrap();
+                self.write.write_u8(num).unwrap();
+            },
+            Constant::I16(num) => {
+                self.write.write_u8(ConstantTag::I16 as u8).unwrap();
+                self.write.write_i16::<BigEndian>(num).unwrap();
+            },
+            Constant::I8(num) => {
+                self.write.write_u8(ConstantTag::I8 as u8).unwrap();
+                self.write.write_i8(num).unwrap();
+            },
             Constant::F64(num) => {
                 self.write.write_u8(ConstantTag::F64 as u8).unwrap();
                 self.write.write_f64::<BigEndian>(num).unwrap();
@@ -218,6 +317,40 @@ impl<'a, W: Write + Seek> ConstantTableWriter<'a, W> {
                 self.write.write_u8(ConstantTag::Str as u8).unwrap();
                 io::write_string(self.write, string).unwrap();
             },
+            Constant::BigInt(ref num) => {
+                self.write.write_u8(ConstantTag::BigInt as u8).unwrap();
+                io::write_blob(self.write, &num.to_signed_bytes_be()[..]).unwrap();
+            },
+            Constant::Char(c) => {
+                self.write.write_u8(ConstantTag::Char as u8).unwrap();
+                self.write.write_u32::<BigEndian>(c as u32).unwrap();
+            },
+            Constant::Bytes(ref bytes) => {
+                self.write.write_u8(ConstantTag::Bytes as u8).unwrap();
+                io::write_blob(self.write, &bytes[..]).unwrap();
+            },
+        }
+    }
+
+}
+
+impl<'a, W: Write + Seek> TypeDeclarationWriter<'a, W> {
+
+    pub fn new(write: &'a mut W) -> TypeDeclarationWriter<'a, W> {
+        TypeDeclarationWriter {
+            write: write,
+        }
+    }
+
+    /// Supertypes must be declared before their subtypes.
+    pub fn write_type_declarations(&mut self, declarations: &[TypeDeclaration]) {
+        self.write.write_u16::<BigEndian>(declarations.len() as u16).unwrap();
+        for declaration in declarations {
+            io::write_string(self.write, &declaration.name[..]).unwrap();
+            self.write.write_u8(declaration.supertype_names.len() as u8).unwrap();
+            for supertype_name in &declaration.supertype_names {
+                io::write_string(self.write, &supertype_name[..]).unwrap();
+            }
         }
     }
 
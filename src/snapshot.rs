@@ -0,0 +1,168 @@
+use std::io::{Error, ErrorKind, Read, Write, Result};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::BigInt;
+
+use error::VmError;
+use io;
+
+
+/// Identifies the bytes as a snapshot.
+const SNAPSHOT_MAGIC: &'static [u8; 4] = b"LSNP";
+
+/// Incremented whenever the layout of a snapshot changes.
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 1;
+
+/// The state of a suspended execution, taken with Context::snapshot. Frames
+/// refer to their functions by name, so that the snapshot can be restored
+/// against an environment in which the functions have other IDs.
+pub struct Snapshot {
+    /// The stack up to the operand stack top of the innermost frame.
+    pub stack: Vec<u64>,
+
+    /// The heap objects, indexed by their handles.
+    pub big_ints: Vec<BigInt>,
+
+    /// The innermost frame comes last.
+    pub frames: Vec<FrameSnapshot>,
+
+    /// Whether the instruction at a breakpoint executes on resumption.
+    pub skip_breakpoint: bool,
+}
+
+pub struct FrameSnapshot {
+    pub function_name: String,
+
+    /// The bytecode fingerprint of the function when the snapshot was taken.
+    pub fingerprint: u64,
+
+    pub inst_index: usize,
+    pub stack_bottom: usize,
+    pub operands_bottom: usize,
+    pub op_stack_top: usize,
+    pub stack_return: usize,
+}
+
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Fails with VmError::InvalidSnapshot if the bytes are malformed, have
+    /// been written by another version of the format or are followed by
+    /// trailing bytes. Lengths are checked against the remaining bytes before
+    /// anything is allocated for them.
+    pub fn from_bytes(bytes: &[u8]) -> ::std::result::Result<Snapshot, VmError> {
+        let mut read = bytes;
+        let snapshot = try!(Snapshot::from_slice(&mut read).map_err(|error| {
+            VmError::InvalidSnapshot(format!("{}", error))
+        }));
+
+        match snapshot {
+            Some(..) if !read.is_empty() => {
+                Err(VmError::InvalidSnapshot(format!("{} trailing bytes.", read.len())))
+            },
+            Some(snapshot) => Ok(snapshot),
+            None => Err(VmError::InvalidSnapshot("Unknown format or format version.".to_string())),
+        }
+    }
+
+    pub fn write(&self, write: &mut Write) -> Result<()> {
+        try!(write.write_all(SNAPSHOT_MAGIC));
+        try!(write.write_u16::<BigEndian>(SNAPSHOT_FORMAT_VERSION));
+        try!(write.write_u8(self.skip_breakpoint as u8));
+
+        try!(write.write_u32::<BigEndian>(self.stack.len() as u32));
+        for slot in &self.stack {
+            try!(write.write_u64::<BigEndian>(*slot));
+        }
+
+        try!(write.write_u32::<BigEndian>(self.big_ints.len() as u32));
+        for num in &self.big_ints {
+            let bytes = num.to_signed_bytes_be();
+            try!(write.write_u32::<BigEndian>(bytes.len() as u32));
+            try!(write.write_all(&bytes[..]));
+        }
+
+        try!(write.write_u16::<BigEndian>(self.frames.len() as u16));
+        for frame in &self.frames {
+            try!(io::write_string(write, &frame.function_name));
+            try!(write.write_u64::<BigEndian>(frame.fingerprint));
+            for position in &[frame.inst_index, frame.stack_bottom, frame.operands_bottom,
+                              frame.op_stack_top, frame.stack_return] {
+                try!(write.write_u64::<BigEndian>(*position as u64));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns None if the magic bytes or the format version do not match.
+    /// Advances the slice past the snapshot.
+    fn from_slice(read: &mut &[u8]) -> Result<Option<Snapshot>> {
+        let mut magic = [0; 4];
+        try!(read.read_exact(&mut magic));
+        if &magic != SNAPSHOT_MAGIC || try!(read.read_u16::<BigEndian>()) != SNAPSHOT_FORMAT_VERSION {
+            return Ok(None);
+        }
+        let skip_breakpoint = try!(read.read_u8()) != 0;
+
+        let stack_length = try!(read.read_u32::<BigEndian>());
+        try!(expect_remaining(read, stack_length as usize, 8));
+        let mut stack = Vec::with_capacity(stack_length as usize);
+        for _ in 0..stack_length {
+            stack.push(try!(read.read_u64::<BigEndian>()));
+        }
+
+        let big_int_count = try!(read.read_u32::<BigEndian>());
+        let mut big_ints = Vec::new();
+        for _ in 0..big_int_count {
+            let length = try!(read.read_u32::<BigEndian>()) as usize;
+            try!(expect_remaining(read, length, 1));
+            let mut bytes = vec![0; length];
+            try!(read.read_exact(&mut bytes[..]));
+            big_ints.push(BigInt::from_signed_bytes_be(&bytes[..]));
+        }
+
+        let frame_count = try!(read.read_u16::<BigEndian>());
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            let name_length = try!(read.read_u16::<BigEndian>()) as usize;
+            try!(expect_remaining(read, name_length, 1));
+            let mut name = vec![0; name_length];
+            try!(read.read_exact(&mut name[..]));
+            let name = try!(String::from_utf8(name).map_err(|error| Error::new(ErrorKind::InvalidData, error)));
+            let fingerprint = try!(read.read_u64::<BigEndian>());
+            let mut positions = [0; 5];
+            for position in positions.iter_mut() {
+                *position = try!(read.read_u64::<BigEndian>()) as usize;
+            }
+            frames.push(FrameSnapshot {
+                function_name: name,
+                fingerprint: fingerprint,
+                inst_index: positions[0],
+                stack_bottom: positions[1],
+                operands_bottom: positions[2],
+                op_stack_top: positions[3],
+                stack_return: positions[4],
+            });
+        }
+
+        Ok(Some(Snapshot {
+            stack: stack,
+            big_ints: big_ints,
+            frames: frames,
+            skip_breakpoint: skip_breakpoint,
+        }))
+    }
+}
+
+/// Fails unless count elements of the given size can still be read.
+fn expect_remaining(read: &[u8], count: usize, size: usize) -> Result<()> {
+    match count.checked_mul(size) {
+        Some(bytes) if bytes <= read.len() => Ok(()),
+        _ => Err(Error::new(ErrorKind::UnexpectedEof, "A length exceeds the remaining bytes.")),
+    }
+}
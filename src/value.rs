@@ -0,0 +1,148 @@
+use num::BigInt;
+
+use bytecode::Type;
+
+
+/// A typed value that is passed to or returned from a function. Values are
+/// checked against the signature of the function.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    BigInt(BigInt),
+}
+
+/// Converts a Rust value, such as a tuple of numbers, into function arguments.
+pub trait IntoValues {
+    fn into_values(self) -> Vec<Value>;
+}
+
+/// Converts function results into a Rust value, such as a tuple of numbers.
+/// Returns None if the count or the types of the results do not match.
+pub trait FromValues: Sized {
+    fn from_values(values: Vec<Value>) -> Option<Self>;
+}
+
+/// Extracts a single Rust value of the exact type.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+
+impl Value {
+    pub fn value_type(&self) -> Type {
+        match *self {
+            Value::I8(..) => Type::I8,
+            Value::I16(..) => Type::I16,
+            Value::I32(..) => Type::I32,
+            Value::I64(..) => Type::I64,
+            Value::U8(..) => Type::U8,
+            Value::U16(..) => Type::U16,
+            Value::U32(..) => Type::U32,
+            Value::U64(..) => Type::U64,
+            Value::F32(..) => Type::F32,
+            Value::F64(..) => Type::F64,
+            Value::BigInt(..) => Type::BigInt,
+        }
+    }
+}
+
+macro_rules! value_conversion {
+    ( $( $t:ty => $variant:ident ),* ) => {
+        $(
+            impl From<$t> for Value {
+                fn from(value: $t) -> Value {
+                    Value::$variant(value)
+                }
+            }
+
+            impl FromValue for $t {
+                fn from_value(value: Value) -> Option<$t> {
+                    match value {
+                        Value::$variant(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+value_conversion!(
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64,
+    u8 => U8, u16 => U16, u32 => U32, u64 => U64,
+    f32 => F32, f64 => F64, BigInt => BigInt
+);
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Option<Value> {
+        Some(value)
+    }
+}
+
+impl IntoValues for Vec<Value> {
+    fn into_values(self) -> Vec<Value> {
+        self
+    }
+}
+
+impl<'a> IntoValues for &'a [Value] {
+    fn into_values(self) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl FromValues for Vec<Value> {
+    fn from_values(values: Vec<Value>) -> Option<Vec<Value>> {
+        Some(values)
+    }
+}
+
+macro_rules! tuple_conversion {
+    ( $count:expr; $( $name:ident ),* ) => {
+        impl<$( $name ),*> IntoValues for ($( $name, )*) where $( $name: Into<Value> ),* {
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<Value> {
+                let ($( $name, )*) = self;
+                vec![$( $name.into() ),*]
+            }
+        }
+
+        impl<$( $name ),*> FromValues for ($( $name, )*) where $( $name: FromValue ),* {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn from_values(values: Vec<Value>) -> Option<($( $name, )*)> {
+                if values.len() != $count {
+                    return None;
+                }
+                let mut values = values.into_iter();
+                $( let $name = try_opt!($name::from_value(values.next().unwrap())); )*
+                Some(($( $name, )*))
+            }
+        }
+    };
+}
+
+macro_rules! try_opt {
+    ( $e:expr ) => {
+        match $e {
+            Some(value) => value,
+            None => return None,
+        }
+    };
+}
+
+tuple_conversion!(0; );
+tuple_conversion!(1; A);
+tuple_conversion!(2; A, B);
+tuple_conversion!(3; A, B, C);
+tuple_conversion!(4; A, B, C, D);
+tuple_conversion!(5; A, B, C, D, E);
+tuple_conversion!(6; A, B, C, D, E, F);
@@ -0,0 +1,48 @@
+extern crate lore;
+
+mod common;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+
+use common::fetched;
+
+
+#[test]
+fn typed_arithmetic_is_specialized_on_fetch() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "poly",
+        Sizes::new(1, 1, 1, 2),
+        vec![
+            Constant::F64(1.5),
+        ],
+        vec![
+            Instruction::Load(0),
+            Instruction::Dup,
+            Instruction::Mul(Type::F64),
+            Instruction::Cst(0),
+            Instruction::Sub(Type::F64),
+            Instruction::Ret(1),
+        ],
+    );
+    match function.instructions {
+        Instructions::Bytecode(ref insts) => {
+            assert!(match insts[2] { Instruction::MulF64 => true, _ => false });
+            assert!(match insts[4] { Instruction::SubF64 => true, _ => false });
+            assert_eq!(format!("{:?}", insts[2]), "mul[f64]");
+            assert!(match insts[4].generic() { Instruction::Sub(Type::F64) => true, _ => false });
+        },
+        Instructions::File {..} => panic!("Expected the bytecode to be loaded."),
+    }
+
+    let mut context = Context::new(1024);
+    for engine in vec![Engine::Threaded, Engine::Switch] {
+        context.set_engine(engine);
+        let results = context.run_raw(function, &vec![3.0f64.to_bits()]).unwrap();
+        assert_eq!(f64::from_bits(results[0]), 7.5);
+    }
+}
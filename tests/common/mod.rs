@@ -0,0 +1,21 @@
+//! Helpers shared by the integration tests.
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::cst::*;
+use lore::environment::*;
+use lore::function::*;
+
+/// Registers a function with the constants and instructions in the
+/// environment and fetches it.
+pub fn fetched<'a>(environment: &'a mut Environment, name: &str, sizes: Sizes,
+                   constants: Vec<Constant>, instructions: Vec<Instruction>) -> &'a Function {
+    let id = environment.register_function(Function::new(
+        name.to_string(),
+        sizes,
+        Arc::new(ConstantTable::new(constants)),
+        Instructions::Bytecode(instructions),
+    ));
+    environment.fetch_function_by_id(id).unwrap()
+}
@@ -0,0 +1,378 @@
+extern crate lore;
+
+mod common;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+use lore::value::*;
+
+use common::fetched;
+
+
+#[test]
+fn threaded_and_switch_engines_agree() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "mixed",
+        Sizes::new(1, 2, 3, 3),
+        vec![
+            Constant::I64(-7),
+            Constant::F64(0.5),
+            Constant::Str("Not a number.".to_string()),
+        ],
+        vec![
+            Instruction::Load(0),
+            Instruction::Cst(0),
+            Instruction::Mul(Type::I64),
+            Instruction::Load(1),
+            Instruction::Div(Type::I64),
+            Instruction::Conv(Type::I64, Type::F64),
+            Instruction::Cst(1),
+            Instruction::Add(Type::F64),
+            Instruction::Dup,
+            Instruction::Store(2),
+            Instruction::Pop,
+            Instruction::Load(2),
+            Instruction::Ret(1),
+        ],
+    );
+    assert!(function.threaded_code.is_some());
+
+    let mut context = Context::new(1024);
+    let arguments = vec![6, 4];
+    let threaded = context.run_raw(function, &arguments).unwrap();
+    context.set_engine(Engine::Switch);
+    let switch = context.run_raw(function, &arguments).unwrap();
+    assert_eq!(threaded, switch);
+    assert_eq!(f64::from_bits(threaded[0]), -9.5);
+
+    for engine in vec![Engine::Threaded, Engine::Switch] {
+        context.set_engine(engine);
+        let fault = context.run_raw(function, &vec![6, 0]).unwrap_err();
+        match fault.error {
+            VmError::DivisionByZero => { },
+            ref error => panic!("Expected a division by zero, but got {:?}.", error),
+        }
+        assert_eq!(fault.trace.frames[0].inst_index, 4);
+    }
+}
+
+#[test]
+fn register_engine_agrees_with_stack_engines() {
+    // Computes (a * b + a) / b twice over, keeping intermediate values in
+    // locals and on the operand stack.
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "shuffle",
+        Sizes::new(1, 2, 3, 4),
+        vec![
+            Constant::I64(3),
+        ],
+        vec![
+            Instruction::Load(0),
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Mul(Type::I64),
+            Instruction::Add(Type::I64),
+            Instruction::Store(2),
+            Instruction::Load(2),
+            Instruction::Load(2),
+            Instruction::Load(1),
+            Instruction::Div(Type::I64),
+            Instruction::Store(2),
+            Instruction::Load(2),
+            Instruction::Add(Type::I64),
+            Instruction::Dup,
+            Instruction::Cst(0),
+            Instruction::Rem(Type::I64),
+            Instruction::Cmp(Type::I64),
+            Instruction::Conv(Type::I32, Type::I64),
+            Instruction::Ret(1),
+        ],
+    );
+    assert!(function.register_code.is_some());
+
+    let mut context = Context::new(1024);
+    let mut results = Vec::new();
+    for engine in vec![Engine::Switch, Engine::Threaded, Engine::Register] {
+        context.set_engine(engine);
+        results.push(context.run_raw(function, &vec![6, 4]).unwrap());
+
+        let fault = context.run_raw(function, &vec![6, 0]).unwrap_err();
+        assert_eq!(fault.trace.frames[0].inst_index, 9);
+    }
+    assert_eq!(results[0], vec![1]);
+    assert_eq!(results[0], results[1]);
+    assert_eq!(results[0], results[2]);
+}
+
+#[test]
+fn run_returns_32_bit_values_in_the_low_bits() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "narrow",
+        Sizes::new(3, 2, 2, 3),
+        vec![
+            Constant::I32(-5),
+            Constant::U32(0xFFFFFFFF),
+            Constant::F32(0.25),
+        ],
+        vec![
+            Instruction::Load(0),
+            Instruction::Cst(0),
+            Instruction::Mul(Type::I32),
+            Instruction::Load(1),
+            Instruction::Cst(1),
+            Instruction::Add(Type::U32),
+            Instruction::Load(0),
+            Instruction::Conv(Type::I32, Type::F32),
+            Instruction::Cst(2),
+            Instruction::Add(Type::F32),
+            Instruction::Ret(3),
+        ],
+    );
+
+    let mut context = Context::new(1024);
+    for engine in vec![Engine::Switch, Engine::Threaded, Engine::Register] {
+        context.set_engine(engine);
+        let results = context.run_raw(function, &vec![3, 2]).unwrap();
+        assert_eq!(results[0], -15i64 as u64);
+        assert_eq!(results[1], 1);
+        assert_eq!(results[2], 3.25f32.to_bits() as u64);
+    }
+}
+
+#[test]
+fn stack_grows_up_to_its_maximum() {
+    let mut environment = Environment::new();
+    let small = environment.register_function(Function::new(
+        "small".to_string(),
+        Sizes::new(1, 1, 1, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Dup,
+            Instruction::Mul(Type::U64),
+            Instruction::Ret(1),
+        ]),
+    ));
+    let large = environment.register_function(Function::new(
+        "large".to_string(),
+        Sizes::new(0, 0, 0, 40),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![]),
+    ));
+
+    let mut context = Context::with_stack_limits(2, 32);
+    assert_eq!(context.stack_length(), 2);
+    assert_eq!(context.run_raw(environment.fetch_function_by_id(small).unwrap(), &vec![7]).unwrap(), vec![49]);
+    assert!(context.stack_length() >= 4);
+
+    match context.run_raw(environment.fetch_function_by_id(large).unwrap(), &vec![]) {
+        Err(Fault { error: VmError::StackOverflow { required: 40, available: 32, .. }, .. }) => { },
+        result => panic!("Expected a stack overflow, but got {:?}.", result),
+    }
+    assert!(context.stack_length() <= 32);
+
+    context.set_max_stack_length(64);
+    assert!(context.run_raw(environment.fetch_function_by_id(large).unwrap(), &vec![]).is_ok());
+    assert_eq!(context.stack_length(), 40);
+    assert_eq!(context.run_raw(environment.fetch_function_by_id(small).unwrap(), &vec![3]).unwrap(), vec![9]);
+
+    // The byte size of the stack would overflow.
+    let mut unbounded = Context::with_stack_limits(4, usize::max_value());
+    let stack_bottom = usize::max_value() / 4;
+    match unbounded.call(environment.fetch_function_by_id(large).unwrap(), stack_bottom, 0) {
+        Err(Fault { error: VmError::OutOfMemory { .. }, .. }) => { },
+        result => panic!("Expected to run out of memory, but got {:?}.", result),
+    }
+    assert_eq!(unbounded.stack_length(), 4);
+    assert_eq!(unbounded.run_raw(environment.fetch_function_by_id(small).unwrap(), &vec![3]).unwrap(), vec![9]);
+}
+
+#[test]
+fn checked_mode_rejects_accesses_outside_of_the_frame() {
+    let mut environment = Environment::new();
+    let bad_local = environment.register_function(Function::new(
+        "bad_local".to_string(),
+        Sizes::new(1, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(3),
+            Instruction::Ret(1),
+        ]),
+    ));
+    let underflow = environment.register_function(Function::new(
+        "underflow".to_string(),
+        Sizes::new(0, 0, 0, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Nop,
+            Instruction::Pop,
+        ]),
+    ));
+    let overflow = environment.register_function(Function::new(
+        "overflow".to_string(),
+        Sizes::new(0, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Dup,
+        ]),
+    ));
+
+    let mut context = Context::new(1024);
+    context.set_checked(true);
+    assert!(context.is_checked());
+
+    let fault = context.run_raw(environment.fetch_function_by_id(bad_local).unwrap(), &vec![5]).unwrap_err();
+    match fault.error {
+        VmError::InvalidLocal { local: 3, locals_count: 1 } => { },
+        ref error => panic!("Expected an invalid local, but got {:?}.", error),
+    }
+    assert_eq!(fault.trace.frames[0].inst_index, 0);
+
+    let fault = context.run_raw(environment.fetch_function_by_id(underflow).unwrap(), &vec![]).unwrap_err();
+    match fault.error {
+        VmError::OperandStackUnderflow { depth: 0, required: 1 } => { },
+        ref error => panic!("Expected an operand stack underflow, but got {:?}.", error),
+    }
+    assert_eq!(fault.trace.frames[0].inst_index, 1);
+
+    match context.run_raw(environment.fetch_function_by_id(overflow).unwrap(), &vec![5]).unwrap_err().error {
+        VmError::OperandStackOverflow { max_operands: 1 } => { },
+        ref error => panic!("Expected an operand stack overflow, but got {:?}.", error),
+    }
+
+    // Well-formed bytecode runs as usual.
+    let square = environment.register_function(Function::new(
+        "square".to_string(),
+        Sizes::new(1, 1, 1, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Dup,
+            Instruction::Mul(Type::I64),
+            Instruction::Ret(1),
+        ]),
+    ));
+    assert_eq!(context.run_raw(environment.fetch_function_by_id(square).unwrap(), &vec![-4i64 as u64]).unwrap(), vec![16]);
+}
+
+#[test]
+fn deterministic_floats_canonicalize_nan_and_saturate() {
+    let mut environment = Environment::new();
+    let add = environment.register_function(Function::new(
+        "add".to_string(),
+        Sizes::new(1, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Add(Type::F64),
+            Instruction::Ret(1),
+        ]),
+    ));
+    let mut pass = Function::new(
+        "pass".to_string(),
+        Sizes::new(1, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Ret(1),
+        ]),
+    );
+    let untyped = environment.register_function(Function::new(
+        "untyped".to_string(),
+        Sizes::new(1, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Ret(1),
+        ]),
+    ));
+    pass.signature = Some(Signature::new(vec![Type::F64], vec![Type::F64]));
+    let pass = environment.register_function(pass);
+    let constants = environment.register_function(Function::new(
+        "constants".to_string(),
+        Sizes::new(2, 0, 0, 2),
+        Arc::new(ConstantTable::new(vec![
+            Constant::F64(f64::from_bits(0x7ff0000000000123)),
+            Constant::F32(f32::from_bits(0x7f800123)),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Cst(0),
+            Instruction::Cst(1),
+            Instruction::Ret(2),
+        ]),
+    ));
+
+    let payload_nan = 0x7ff0000000000123u64;
+    let mut context = Context::new(1024);
+    let result = context.run_raw(environment.fetch_function_by_id(add).unwrap(), &vec![payload_nan, 1.0f64.to_bits()]).unwrap();
+    assert!(f64::from_bits(result[0]).is_nan());
+
+    context.set_deterministic_floats(true);
+    assert!(context.has_deterministic_floats());
+    for engine in vec![Engine::Switch, Engine::Threaded, Engine::Register] {
+        context.set_engine(engine);
+        let result = context.run_raw(environment.fetch_function_by_id(add).unwrap(), &vec![payload_nan, 1.0f64.to_bits()]).unwrap();
+        assert_eq!(result, vec![0x7ff8000000000000]);
+        let result = context.run_raw(environment.fetch_function_by_id(add).unwrap(), &vec![2.5f64.to_bits(), 1.0f64.to_bits()]).unwrap();
+        assert_eq!(result, vec![3.5f64.to_bits()]);
+
+        // Arguments and constants are canonicalized without any arithmetic.
+        let result = context.run_raw(environment.fetch_function_by_id(pass).unwrap(), &vec![payload_nan]).unwrap();
+        assert_eq!(result, vec![0x7ff8000000000000]);
+        let result = context.run(environment.fetch_function_by_id(pass).unwrap(), (f64::from_bits(payload_nan),)).unwrap();
+        match result[0] {
+            Value::F64(value) => assert_eq!(value.to_bits(), 0x7ff8000000000000),
+            ref value => panic!("Expected an f64, but got {:?}.", value),
+        }
+        let result = context.run_raw(environment.fetch_function_by_id(constants).unwrap(), &vec![]).unwrap();
+        assert_eq!(result, vec![0x7ff8000000000000, 0x7fc00000]);
+
+        // Without a signature, an argument that is only moved keeps its bits.
+        let result = context.run_raw(environment.fetch_function_by_id(untyped).unwrap(), &vec![payload_nan]).unwrap();
+        assert_eq!(result, vec![payload_nan]);
+    }
+
+    // Every integer type saturates, and signed results are sign-extended.
+    let nan = ::std::f64::NAN;
+    let conversions = vec![
+        (Type::I64, vec![(1e30f64, i64::max_value()), (-1e30, i64::min_value()), (nan, 0), (-7.9, -7)]),
+        (Type::I32, vec![(1e20, i32::max_value() as i64), (-1e20, i32::min_value() as i64), (nan, 0), (-7.9, -7)]),
+        (Type::I16, vec![(1e6, 32767), (-1e6, -32768), (nan, 0), (-7.9, -7)]),
+        (Type::I8, vec![(300.0, 127), (-300.0, -128), (nan, 0), (-7.9, -7)]),
+        (Type::U64, vec![(1e30, -1), (-5.0, 0), (nan, 0), (7.9, 7)]),
+        (Type::U32, vec![(1e20, u32::max_value() as i64), (-5.0, 0), (nan, 0), (7.9, 7)]),
+        (Type::U16, vec![(1e6, 65535), (-5.0, 0), (nan, 0), (7.9, 7)]),
+        (Type::U8, vec![(300.0, 255), (-5.0, 0), (nan, 0), (7.9, 7)]),
+    ];
+    for (t, cases) in conversions {
+        let id = environment.register_function(Function::new(
+            format!("to_{:?}", t),
+            Sizes::new(1, 1, 1, 1),
+            Arc::new(ConstantTable::new(vec![])),
+            Instructions::Bytecode(vec![
+                Instruction::Load(0),
+                Instruction::Conv(Type::F64, t),
+                Instruction::Ret(1),
+            ]),
+        ));
+        let function = environment.fetch_function_by_id(id).unwrap();
+        for (value, expected) in cases {
+            assert_eq!(context.run_raw(function, &vec![value.to_bits()]).unwrap(), vec![expected as u64],
+                       "Converting {} to {:?}.", value, t);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+extern crate lore;
+extern crate num;
+
+mod common;
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::scribe::*;
+use lore::cst::*;
+
+use num::BigInt;
+
+use common::fetched;
+
+
+#[test]
+fn constant_table_roundtrip() {
+    let constants = vec![
+        Constant::I8(-8),
+        Constant::I16(-16),
+        Constant::I32(-32),
+        Constant::I64(-64),
+        Constant::U8(8),
+        Constant::U16(16),
+        Constant::U32(32),
+        Constant::U64(64),
+        Constant::F32(0.5),
+        Constant::F64(-0.25),
+        Constant::Str("lore".to_string()),
+        Constant::BigInt(BigInt::from(-1) << 100),
+        Constant::Char('λ'),
+        Constant::Bytes(vec![0x00, 0xFF, 0x10]),
+    ];
+    let expected: Vec<String> = constants.iter().map(|c| format!("{:?}", c)).collect();
+
+    let mut cst_file = File::create("all_constants.cst").unwrap();
+    ConstantTableWriter::new(&mut cst_file).write_constant_table(&ConstantTable::new(constants));
+
+    let table = ConstantTable::from_file(Path::new("all_constants")).unwrap();
+    let actual: Vec<String> = table.table.iter().map(|c| format!("{:?}", c)).collect();
+    assert_eq!(actual, expected);
+
+    // Longer than 64 KiB.
+    let huge = BigInt::from(-3) << 600000usize;
+    let blob = vec![0xA5; 70000];
+    let mut cst_file = File::create("huge_big_int.cst").unwrap();
+    ConstantTableWriter::new(&mut cst_file).write_constant_table(&ConstantTable::new(vec![
+        Constant::BigInt(huge.clone()),
+        Constant::Bytes(blob.clone()),
+    ]));
+    let table = ConstantTable::from_file(Path::new("huge_big_int")).unwrap();
+    match table.table[0] {
+        Constant::BigInt(ref num) => assert!(*num == huge),
+        ref constant => panic!("Expected a big integer, but got {:?}.", constant),
+    }
+    match table.table[1] {
+        Constant::Bytes(ref bytes) => assert!(*bytes == blob),
+        ref constant => panic!("Expected a byte blob, but got {:?}.", constant),
+    }
+
+    // A length of u32::MAX fails at the end of the input.
+    match ConstantTable::from_read(&mut &[0x00, 0x01, 0x07, 0xFF, 0xFF, 0xFF, 0xFF, 0x01][..]) {
+        Err(error) => assert_eq!(error.kind(), ErrorKind::UnexpectedEof),
+        Ok(..) => panic!("Expected a truncated big integer to be rejected."),
+    }
+
+    // A surrogate and a code point beyond the Unicode range.
+    for bytes in &[[0x00, 0x01, 0x0C, 0x00, 0x00, 0xD8, 0x00], [0x00, 0x01, 0x0C, 0x00, 0x11, 0x00, 0x00]] {
+        match ConstantTable::from_read(&mut &bytes[..]) {
+            Err(error) => assert_eq!(error.kind(), ErrorKind::InvalidData),
+            Ok(..) => panic!("Expected an invalid char constant to be rejected."),
+        }
+    }
+}
+
+#[test]
+fn bytes_constants_are_only_rejected_when_pushed() {
+    let blob_table = Arc::new(ConstantTable::new(vec![
+        Constant::Bytes(vec![0xCA, 0xFE]),
+        Constant::U64(11),
+    ]));
+
+    let mut environment = Environment::new();
+    let skipping = environment.register_function(Function::new(
+        "skipping".to_string(),
+        Sizes::new(1, 0, 0, 1),
+        blob_table.clone(),
+        Instructions::Bytecode(vec![
+            Instruction::Cst(1),
+            Instruction::Ret(1),
+        ]),
+    ));
+    let pushing = environment.register_function(Function::new(
+        "pushing".to_string(),
+        Sizes::new(1, 0, 0, 1),
+        blob_table,
+        Instructions::Bytecode(vec![
+            Instruction::Cst(0),
+            Instruction::Ret(1),
+        ]),
+    ));
+
+    let mut context = Context::new(1024);
+    assert_eq!(context.run_raw(environment.fetch_function_by_id(skipping).unwrap(), &vec![]).unwrap(), vec![11]);
+    match environment.fetch_function_by_id(pushing) {
+        Err(VmError::InvalidConstant { index: 0, .. }) => { },
+        result => panic!("Expected an invalid constant, but got {:?}.", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn small_constants_fill_whole_slots() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "small_constants",
+        Sizes::new(5, 0, 0, 5),
+        vec![
+            Constant::I8(-8),
+            Constant::I16(-1600),
+            Constant::U8(200),
+            Constant::U16(60000),
+            Constant::Char('λ'),
+        ],
+        vec![
+            Instruction::Cst(0),
+            Instruction::Cst(1),
+            Instruction::Cst(2),
+            Instruction::Cst(3),
+            Instruction::Cst(4),
+            Instruction::Ret(5),
+        ],
+    );
+
+    // Signed integers are sign-extended, and chars are pushed as their code point.
+    let mut context = Context::new(1024);
+    assert_eq!(context.run_raw(function, &vec![]).unwrap(),
+               vec![-8i64 as u64, -1600i64 as u64, 200, 60000, 'λ' as u64]);
+}
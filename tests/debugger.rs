@@ -0,0 +1,106 @@
+extern crate lore;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+use lore::debugger::*;
+
+
+#[test]
+fn single_step() {
+    let function = Function::new(
+        "sub".to_string(),
+        Sizes::new(1, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Sub(Type::I64),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    let function = environment.fetch_function_by_id(id).unwrap();
+
+    let mut context = Context::new(1024);
+    context.start(function, &vec![5, 8]).unwrap();
+    assert!(context.is_suspended());
+    assert_eq!(context.current_function_id(), Some(id));
+    assert_eq!(context.current_inst_index(), Some(0));
+    assert_eq!(context.local_count(), 2);
+    assert_eq!(context.local_i64(1), Some(8));
+    assert_eq!(context.local_i64(2), None);
+
+    context.step(function).unwrap();
+    context.step(function).unwrap();
+    assert_eq!(context.current_inst_index(), Some(2));
+    assert_eq!(context.operand_count(), 2);
+    assert_eq!(context.operand_i64(0), Some(5));
+    assert_eq!(context.operand_i64(1), Some(8));
+
+    context.step(function).unwrap();
+    assert_eq!(context.operand_count(), 1);
+    assert_eq!(context.operand_i64(0), Some(-3));
+
+    match context.step(function).unwrap() {
+        Step::Finished(results) => assert_eq!(results, vec![-3i64 as u64]),
+        Step::Paused => panic!("Expected the function to finish."),
+    }
+    assert!(!context.is_suspended());
+    assert_eq!(context.current_inst_index(), None);
+}
+
+#[test]
+fn breakpoints_and_watchpoints() {
+    let function = Function::new(
+        "square_twice".to_string(),
+        Sizes::new(1, 1, 1, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Dup,
+            Instruction::Mul(Type::U64),
+            Instruction::Store(0),
+            Instruction::Load(0),
+            Instruction::Dup,
+            Instruction::Mul(Type::U64),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    let function = environment.fetch_function_by_id(id).unwrap();
+
+    let mut debugger = Debugger::new();
+    debugger.set_breakpoint(id, 2);
+    debugger.set_watchpoint(id, 0);
+
+    let mut context = Context::new(1024);
+    context.attach_debugger(debugger);
+
+    match context.run_raw(function, &vec![3]).unwrap_err().error {
+        VmError::Breakpoint { function_id, inst_index: 2 } => assert_eq!(function_id, id),
+        error => panic!("Expected to hit the breakpoint, but got {:?}.", error),
+    }
+    assert_eq!(context.operand_u64(1), Some(3));
+
+    match context.resume(function).unwrap_err().error {
+        VmError::Watchpoint { local: 0, inst_index: 3, .. } => { },
+        error => panic!("Expected to hit the watchpoint, but got {:?}.", error),
+    }
+    assert_eq!(context.local_u64(0), Some(9));
+
+    context.debugger_mut().unwrap().clear_watchpoint(id, 0);
+    assert_eq!(context.resume(function).unwrap(), vec![81]);
+
+    assert!(context.detach_debugger().is_some());
+    assert_eq!(context.run_raw(function, &vec![3]).unwrap(), vec![81]);
+}
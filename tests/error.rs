@@ -0,0 +1,111 @@
+extern crate lore;
+
+mod common;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+
+use common::fetched;
+
+
+#[test]
+fn assert_traps() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "check_not_negative",
+        Sizes::new(0, 1, 1, 2),
+        vec![
+            Constant::I32(0),
+            Constant::I32(1),
+            Constant::Str("Argument must not be negative.".to_string()),
+        ],
+        vec![
+            Instruction::Load(0),
+            Instruction::Cst(0),
+            Instruction::Cmp(Type::I32),
+            Instruction::Cst(1),
+            Instruction::Add(Type::I32),
+            Instruction::Assert(2),
+            Instruction::Ret(0),
+        ],
+    );
+
+    let mut context = Context::new(1024);
+    assert!(context.run_raw(function, &vec![5]).is_ok());
+
+    match context.run_raw(function, &vec![(-5i64) as u64]) {
+        Err(Fault { error: VmError::Trap(trap), .. }) => {
+            assert_eq!(trap.message, "Argument must not be negative.");
+            assert_eq!(trap.function_name, "check_not_negative");
+            assert_eq!(trap.inst_index, 5);
+        },
+        result => panic!("Expected a trap, but got {:?}.", result),
+    }
+}
+
+#[test]
+fn errors_leave_context_usable() {
+    let function = Function::new(
+        "divide".to_string(),
+        Sizes::new(1, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Div(Type::I64),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    environment.fetch_function_by_id(id).unwrap();
+    let function = environment.get_function_by_id(id);
+
+    let mut context = Context::new(1024);
+    match context.run_raw(function, &vec![1]) {
+        Err(Fault { error: VmError::ArgumentCountMismatch { expected: 2, actual: 1, .. }, .. }) => { },
+        result => panic!("Expected an argument count mismatch, but got {:?}.", result),
+    }
+    let fault = context.run_raw(function, &vec![1, 0]).unwrap_err();
+    match fault.error {
+        VmError::DivisionByZero => { },
+        ref error => panic!("Expected a division by zero, but got {:?}.", error),
+    }
+    assert_eq!(fault.trace.frames.len(), 1);
+    assert_eq!(fault.trace.frames[0].function_name, "divide");
+    assert_eq!(fault.trace.frames[0].inst_index, 2);
+    assert_eq!(
+        fault.disassemble(&environment, 1),
+        concat!(
+            "Division by zero.\n",
+            "    at 'divide' (#0), instruction 2\n",
+            "           1: load $1\n",
+            "     ->    2: div[i64]\n",
+            "           3: ret(1)\n",
+        )
+    );
+    assert_eq!(
+        fault.disassemble(&Environment::new(), 1),
+        concat!(
+            "Division by zero.\n",
+            "    at 'divide' (#0), instruction 2\n",
+            "        <unknown function>\n",
+        )
+    );
+
+    assert_eq!(context.run_raw(function, &vec![-9i64 as u64, 3]).unwrap(), vec![-3i64 as u64]);
+
+    let mut small_context = Context::new(3);
+    match small_context.run_raw(function, &vec![1, 1]) {
+        Err(Fault { error: VmError::StackOverflow { required: 5, available: 3, .. }, .. }) => { },
+        result => panic!("Expected a stack overflow, but got {:?}.", result),
+    }
+}
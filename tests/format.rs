@@ -0,0 +1,79 @@
+extern crate lore;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+use lore::format::*;
+
+
+#[test]
+fn format_templates() {
+    let template = Template::parse("{{total}}: {i64:>6} | {f64:*^9.2} | {u32:08X} | {i32:05}\n").unwrap();
+    assert_eq!(template.placeholder_count(), 4);
+
+    let mut output = String::new();
+    let mut rendered = vec![
+        |spec: &Spec| spec.render_integer(-42i64),
+        |spec: &Spec| spec.render_float(2.5072f64),
+        |spec: &Spec| spec.render_integer(0xBEEFu32),
+        |spec: &Spec| spec.render_integer(-7i32),
+    ].into_iter();
+    for segment in &template.segments {
+        match *segment {
+            Segment::Literal(ref text) => output.push_str(text),
+            Segment::Placeholder(_, ref spec) => output.push_str(&rendered.next().unwrap()(spec)),
+        }
+    }
+    assert_eq!(output, "{total}:    -42 | **2.51*** | 0000BEEF | -0007\n");
+
+    assert!(Template::parse("{f32:x}").is_err());
+    assert!(Template::parse("{i64:.2}").is_err());
+    assert!(Template::parse("{str}").is_err());
+    assert!(Template::parse("{i64").is_err());
+    assert!(Template::parse("{u64:99999999999999999999999}").is_err());
+    assert!(Template::parse("{f64:.65536}").is_err());
+    assert!(Template::parse("{f64:65535.65535}").is_ok());
+}
+
+#[test]
+fn print_fmt_templates_are_parsed_on_fetch() {
+    let printer = |name: &str, template: &str| Function::new(
+        name.to_string(),
+        Sizes::new(1, 1, 1, 3),
+        Arc::new(ConstantTable::new(vec![
+            Constant::Str(template.to_string()),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(0),
+            Instruction::Load(0),
+            Instruction::PrintFmt(0, 2),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let valid = environment.register_function(printer("valid", "{{x}} = {i64:>4} ({i64:x})\n"));
+    let malformed = environment.register_function(printer("malformed", "{i64:>4} ({i64\n"));
+    let mismatched = environment.register_function(printer("mismatched", "{i64}\n"));
+
+    {
+        let function = environment.fetch_function_by_id(valid).unwrap();
+        assert_eq!(function.templates.len(), 1);
+
+        let mut context = Context::new(1024);
+        assert_eq!(context.run_raw(function, &vec![255]).unwrap(), vec![255]);
+    }
+
+    for &id in &[malformed, mismatched] {
+        match environment.fetch_function_by_id(id) {
+            Err(VmError::InvalidFormat(..)) => { },
+            result => panic!("Expected an invalid format, but got {:?}.", result.map(|f| f.name.clone())),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+extern crate lore;
+
+mod common;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::fuel::*;
+
+use common::fetched;
+
+
+#[test]
+fn fuel_suspends_and_resumes() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "affine",
+        Sizes::new(1, 1, 1, 2),
+        vec![
+            Constant::U64(3),
+            Constant::U64(7),
+        ],
+        vec![
+            Instruction::Load(0),
+            Instruction::Cst(0),
+            Instruction::Mul(Type::U64),
+            Instruction::Cst(1),
+            Instruction::Add(Type::U64),
+            Instruction::Ret(1),
+        ],
+    );
+
+    let mut context = Context::new(1024);
+    let mut costs = FuelCosts::uniform();
+    costs.set_cost(Opcode::Mul, 5);
+    context.set_fuel_costs(costs);
+    context.set_fuel(7);
+
+    let fault = context.run_raw(function, &vec![10]).unwrap_err();
+    match fault.error {
+        VmError::OutOfFuel { consumed: 7 } => { },
+        ref error => panic!("Expected to run out of fuel, but got {:?}.", error),
+    }
+    assert_eq!(fault.trace.frames[0].inst_index, 3);
+    assert_eq!(context.remaining_fuel(), Some(0));
+
+    context.add_fuel(3);
+    assert_eq!(context.resume(function).unwrap(), vec![37]);
+    assert_eq!(context.fuel_consumed(), 10);
+    assert_eq!(context.remaining_fuel(), Some(0));
+
+    match context.resume(function) {
+        Err(Fault { error: VmError::NotSuspended, .. }) => { },
+        result => panic!("Expected no suspended execution, but got {:?}.", result),
+    }
+}
@@ -0,0 +1,246 @@
+extern crate lore;
+extern crate num;
+
+mod common;
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::scribe::*;
+use lore::cst::*;
+use lore::value::*;
+
+use num::BigInt;
+
+use common::fetched;
+
+
+#[test]
+fn missing_and_corrupt_files_fail_to_load() {
+    let mut environment = Environment::new();
+    for result in vec![
+        Function::from_file(&mut environment, Path::new("missing_function")).map(|_| ()),
+        environment.fetch_constant_table(Path::new("missing_table")).map(|_| ()),
+        environment.load_types(Path::new("missing_types")).map(|_| ()),
+    ] {
+        match result {
+            Err(VmError::LoadFailed { .. }) => { },
+            result => panic!("Expected a missing file, but got {:?}.", result),
+        }
+    }
+
+    // The constant table of the function does not exist.
+    {
+        let mut file = File::create("without_table.func").unwrap();
+        let mut writer = FunctionWriter::new(&mut file, "without_table", "missing_table", 0);
+        writer.write_operation(Opcode::Nop);
+        writer.finish();
+    }
+    match Function::from_file(&mut environment, Path::new("without_table")) {
+        Err(VmError::LoadFailed { .. }) => { },
+        result => panic!("Expected a missing constant table, but got {:?}.", result.map(|f| f.name)),
+    }
+
+    // The second instruction has an invalid opcode.
+    {
+        let mut cst_file = File::create("empty_table.cst").unwrap();
+        ConstantTableWriter::new(&mut cst_file).write_constant_table(&ConstantTable::new(vec![]));
+
+        let mut file = File::create("corrupt.func").unwrap();
+        {
+            let mut writer = FunctionWriter::new(&mut file, "corrupt", "empty_table", 0);
+            writer.write_operation(Opcode::Nop);
+            writer.finish();
+        }
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(&[0xFF]).unwrap();
+        file.seek(SeekFrom::Start(Function::calculate_instructions_offset("corrupt", "empty_table", None))).unwrap();
+        file.write_all(&[0, 0, 0, 2]).unwrap();
+    }
+    let function = Function::from_file(&mut environment, Path::new("corrupt")).unwrap();
+    let id = environment.register_function(function);
+    match environment.fetch_function_by_id(id) {
+        Err(VmError::LoadFailed { .. }) => { },
+        result => panic!("Expected corrupt instructions, but got {:?}.", result.map(|f| f.name.clone())),
+    }
+}
+
+#[test]
+fn signatures_roundtrip_through_function_files() {
+    {
+        let mut cst_file = File::create("signed_table.cst").unwrap();
+        ConstantTableWriter::new(&mut cst_file).write_constant_table(&ConstantTable::new(vec![]));
+
+        let signature = Signature::new(vec![Type::I32, Type::F64], vec![Type::F64]);
+        let mut file = File::create("signed.func").unwrap();
+        let mut writer = FunctionWriter::with_signature(&mut file, "signed", "signed_table", &signature);
+        writer.write_load(1);
+        writer.write_ret(1);
+        writer.finish();
+
+        let mut file = File::create("unsigned.func").unwrap();
+        let mut writer = FunctionWriter::new(&mut file, "unsigned", "signed_table", 1);
+        writer.write_load(0);
+        writer.write_ret(1);
+        writer.finish();
+
+        // A signature that declares more results than the function returns.
+        let signature = Signature::new(vec![], vec![Type::I32]);
+        let mut file = File::create("misfit.func").unwrap();
+        let mut writer = FunctionWriter::with_signature(&mut file, "misfit", "signed_table", &signature);
+        writer.write_operation(Opcode::Nop);
+        writer.finish();
+
+        // The first version of the format had no header.
+        let mut file = File::create("headless.func").unwrap();
+        file.write_all(&[0, 8]).unwrap();
+        file.write_all(b"headless").unwrap();
+    }
+
+    let mut environment = Environment::new();
+    let signed = Function::from_file(&mut environment, Path::new("signed")).unwrap();
+    {
+        let signature = signed.signature.as_ref().unwrap();
+        assert!(signature.arguments == vec![Type::I32, Type::F64]);
+        assert!(signature.returns == vec![Type::F64]);
+    }
+    let unsigned = Function::from_file(&mut environment, Path::new("unsigned")).unwrap();
+    assert!(unsigned.signature.is_none());
+    for name in &["misfit", "headless"] {
+        match Function::from_file(&mut environment, Path::new(name)) {
+            Err(VmError::LoadFailed { .. }) => { },
+            result => panic!("Expected '{}' to fail to load, but got {:?}.", name, result.map(|f| f.name)),
+        }
+    }
+
+    let signed = environment.register_function(signed);
+    let unsigned = environment.register_function(unsigned);
+    let mut context = Context::new(1024);
+    let results = context.run(environment.fetch_function_by_id(signed).unwrap(), (3i32, 0.5f64)).unwrap();
+    assert_eq!(results, vec![Value::F64(0.5)]);
+    match context.run(environment.fetch_function_by_id(unsigned).unwrap(), (3u64,)).unwrap_err().error {
+        VmError::InvalidSignature { .. } => { },
+        ref error => panic!("Expected a missing signature, but got {:?}.", error),
+    }
+}
+
+#[test]
+fn constants_are_resolved_on_fetch() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "constants",
+        Sizes::new(2, 0, 0, 2),
+        vec![
+            Constant::F32(2.5),
+            Constant::U64(42),
+            Constant::BigInt(BigInt::from(7)),
+        ],
+        vec![
+            Instruction::Cst(1),
+            Instruction::Cst(2),
+            Instruction::Pop,
+            Instruction::Cst(0),
+            Instruction::Ret(2),
+        ],
+    );
+    match function.instructions {
+        Instructions::Bytecode(ref insts) => {
+            assert!(match insts[0] { Instruction::CstBits(1, 42) => true, _ => false });
+            assert!(match insts[1] { Instruction::Cst(2) => true, _ => false });
+            assert_eq!(format!("{:?}", insts[3]), "cst #0");
+        },
+        Instructions::File {..} => panic!("Expected the bytecode to be loaded."),
+    }
+
+    let mut context = Context::new(1024);
+    for engine in vec![Engine::Switch, Engine::Threaded, Engine::Register] {
+        context.set_engine(engine);
+        let results = context.run_raw(function, &vec![]).unwrap();
+        assert_eq!(results[0], 42);
+        assert_eq!(results[1], constant_slot_bits(&Constant::F32(2.5)).unwrap());
+    }
+}
+
+#[test]
+fn constant_mismatches_are_rejected_on_fetch() {
+    let function = Function::new(
+        "cst_of_string".to_string(),
+        Sizes::new(0, 0, 0, 1),
+        Arc::new(ConstantTable::new(vec![
+            Constant::U64(1),
+            Constant::Str("{u64}\n".to_string()),
+            Constant::Str("Not a number.".to_string()),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Cst(0),
+            Instruction::PrintFmt(1, 1),
+            Instruction::Cst(2),
+            Instruction::Pop,
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    match environment.fetch_function_by_id(id) {
+        Err(VmError::InvalidConstant { index: 2, expected: "a number" }) => { },
+        result => panic!("Expected an invalid constant, but got {:?}.", result.err()),
+    }
+
+    // The constants before the invalid one are not resolved either.
+    let function = environment.get_function_by_id(id);
+    match function.instructions {
+        Instructions::Bytecode(ref insts) => {
+            assert!(match insts[0] { Instruction::Cst(0) => true, _ => false });
+        },
+        Instructions::File {..} => panic!("Expected the bytecode to be loaded."),
+    }
+    assert!(function.templates.is_empty());
+    assert!(function.threaded_code.is_none());
+    match environment.fetch_function_by_id(id + 1) {
+        Err(VmError::LoadFailed { .. }) => { },
+        result => panic!("Expected a missing function, but got {:?}.", result.err()),
+    }
+}
+
+#[test]
+fn equal_constant_tables_are_shared_on_fetch() {
+    let table = |zero: f64| Arc::new(ConstantTable::new(vec![
+        Constant::F64(zero),
+        Constant::Str("shared".to_string()),
+    ]));
+    let function = |name: &str, table: Arc<ConstantTable>| Function::new(
+        name.to_string(),
+        Sizes::new(1, 0, 0, 1),
+        table,
+        Instructions::Bytecode(vec![
+            Instruction::Cst(0),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let first = environment.register_function(function("first", table(0.0)));
+    let second = environment.register_function(function("second", table(0.0)));
+    let negative = environment.register_function(function("negative", table(-0.0)));
+    for &id in &[first, second, negative] {
+        environment.fetch_function_by_id(id).unwrap();
+    }
+
+    let first = environment.get_function_by_id(first);
+    let second = environment.get_function_by_id(second);
+    let negative = environment.get_function_by_id(negative);
+    assert!(Arc::ptr_eq(&first.constant_table, &second.constant_table));
+    assert!(!Arc::ptr_eq(&first.constant_table, &negative.constant_table));
+
+    let mut context = Context::new(1024);
+    assert_eq!(context.run_raw(second, &vec![]).unwrap(), vec![0]);
+    assert_eq!(context.run_raw(negative, &vec![]).unwrap(), vec![(-0.0f64).to_bits()]);
+}
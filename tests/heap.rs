@@ -0,0 +1,98 @@
+extern crate lore;
+extern crate num;
+
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::scribe::*;
+use lore::cst::*;
+
+use num::BigInt;
+
+
+#[test]
+fn big_int_arithmetic() {
+    let big = BigInt::from_str("-123456789012345678901234567890").unwrap();
+
+    let mut cst_file = File::create("big_ints.cst").unwrap();
+    ConstantTableWriter::new(&mut cst_file).write_constant_table(&ConstantTable::new(vec![
+        Constant::BigInt(big.clone()),
+    ]));
+    let constant_table = ConstantTable::from_file(Path::new("big_ints")).unwrap();
+
+    // Returns big * arg + big, and how big * arg compares to big.
+    let function = Function::new(
+        "big_int_arithmetic".to_string(),
+        Sizes::new(2, 1, 1, 3),
+        Arc::new(constant_table),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Conv(Type::I64, Type::BigInt),
+            Instruction::Cst(0),
+            Instruction::Mul(Type::BigInt),
+            Instruction::Dup,
+            Instruction::Cst(0),
+            Instruction::Cmp(Type::BigInt),
+            Instruction::Conv(Type::I32, Type::I64),
+            Instruction::Store(0),
+            Instruction::Cst(0),
+            Instruction::Add(Type::BigInt),
+            Instruction::Load(0),
+            Instruction::Ret(2),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+
+    let mut context = Context::new(1024);
+    let results = context.run_raw(environment.fetch_function_by_id(id).unwrap(), &vec![-3i64 as u64]).unwrap();
+    assert_eq!(*context.big_int(results[0]).unwrap(), &big * BigInt::from(-3) + &big);
+    assert_eq!(results[1] as i64, 1);
+}
+
+#[test]
+fn small_integers_convert_to_and_from_big_ints() {
+    // Converts the argument to a big integer and then to the target type.
+    let conversions = vec![
+        (Type::I8, Type::I16, -100i8 as u64, Some(-100i16 as u64)),
+        (Type::U8, Type::U16, 200u8 as u64, Some(200u16 as u64)),
+        (Type::U16, Type::I8, 127u16 as u64, Some(127i8 as u64)),
+        (Type::I16, Type::U8, 255i16 as u64, Some(255u8 as u64)),
+        (Type::I16, Type::I8, -300i16 as u64, None),
+        (Type::U16, Type::U8, 60000u16 as u64, None),
+        (Type::I8, Type::U16, -1i8 as u64, None),
+    ];
+
+    let mut environment = Environment::new();
+    let mut context = Context::new(1024);
+    for (i, (from, to, argument, expected)) in conversions.into_iter().enumerate() {
+        let function = Function::new(
+            format!("conv_{}", i),
+            Sizes::new(1, 1, 1, 1),
+            Arc::new(ConstantTable::new(vec![])),
+            Instructions::Bytecode(vec![
+                Instruction::Load(0),
+                Instruction::Conv(from, Type::BigInt),
+                Instruction::Conv(Type::BigInt, to),
+                Instruction::Ret(1),
+            ]),
+        );
+        let id = environment.register_function(function);
+
+        match (context.run_raw(environment.fetch_function_by_id(id).unwrap(), &vec![argument]), expected) {
+            (Ok(results), Some(expected)) => assert_eq!(results, vec![expected]),
+            (Err(Fault { error: VmError::ConversionOverflow { to: actual, .. }, .. }), None) => {
+                assert!(actual == to);
+            },
+            (result, _) => panic!("Unexpected result of conversion #{}: {:?}", i, result),
+        }
+    }
+}
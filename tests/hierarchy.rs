@@ -0,0 +1,94 @@
+extern crate lore;
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::scribe::*;
+use lore::cst::*;
+use lore::hierarchy::*;
+
+
+#[test]
+fn type_hierarchy() {
+    let declarations = vec![
+        TypeDeclaration::new("Animal".to_string(), vec![]),
+        TypeDeclaration::new("Pet".to_string(), vec![]),
+        TypeDeclaration::new("Cat".to_string(), vec!["Animal".to_string(), "Pet".to_string()]),
+    ];
+
+    let mut types_file = File::create("animals.types").unwrap();
+    TypeDeclarationWriter::new(&mut types_file).write_type_declarations(&declarations);
+
+    let mut environment = Environment::new();
+    let ids = environment.load_types(Path::new("animals")).unwrap();
+    let (animal, pet, cat) = (ids[0], ids[1], ids[2]);
+
+    assert!(environment.is_subtype(cat, animal));
+    assert!(environment.is_subtype(cat, pet));
+    assert!(environment.is_subtype(cat, cat));
+    assert!(!environment.is_subtype(animal, cat));
+    assert!(!environment.is_subtype(pet, animal));
+
+    // Stacked diamonds reach the bottom type on 2^64 paths.
+    let unrelated = environment.register_type("Unrelated", &[]);
+    let mut bottom = environment.register_type("Diamond0", &[]);
+    for level in 1..65 {
+        let left = environment.register_type(&format!("Left{}", level), &[bottom]);
+        let right = environment.register_type(&format!("Right{}", level), &[bottom]);
+        bottom = environment.register_type(&format!("Diamond{}", level), &[left, right]);
+    }
+    assert!(!environment.is_subtype(bottom, unrelated));
+    assert!(environment.is_subtype(bottom, environment.get_type_by_name("Diamond0").unwrap().id));
+
+    // Types cannot be registered twice.
+    match environment.load_types(Path::new("animals")) {
+        Err(VmError::LoadFailed { .. }) => { },
+        result => panic!("Expected the types to be rejected, but got {:?}.", result),
+    }
+
+    let is_animal = Function::new(
+        "is_animal".to_string(),
+        Sizes::new(1, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Cast(pet),
+            Instruction::IsInstance(animal),
+            Instruction::Ret(1),
+        ]),
+    );
+    let id = environment.register_function(is_animal);
+
+    let mut context = Context::new(1024);
+    context.set_type_hierarchy(environment.type_hierarchy());
+    let results = context.run_raw(environment.fetch_function_by_id(id).unwrap(), &vec![cat as u64]).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0], 1);
+
+    // The operand is a type ID, and wider slots do not wrap around to one.
+    assert_eq!(format!("{:?}", Instruction::IsInstance(animal)), format!("isinstance[type id] @{}", animal));
+    let is_pet = Function::new(
+        "is_pet".to_string(),
+        Sizes::new(1, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::IsInstance(pet),
+            Instruction::Ret(1),
+        ]),
+    );
+    let is_pet = environment.register_function(is_pet);
+    let results = context.run_raw(environment.fetch_function_by_id(is_pet).unwrap(), &vec![(1 << 32) | cat as u64]).unwrap();
+    assert_eq!(results, vec![0]);
+    let function = environment.get_function_by_id(id);
+    match context.run_raw(function, &vec![(1 << 32) | cat as u64]) {
+        Err(Fault { error: VmError::CastFailed { from, .. }, .. }) => assert_eq!(from, u32::max_value()),
+        result => panic!("Expected the cast to fail, but got {:?}.", result),
+    }
+}
@@ -0,0 +1,165 @@
+extern crate lore;
+
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+use lore::interrupt::*;
+use lore::observer::*;
+
+use common::fetched;
+
+
+#[test]
+fn interrupt_from_another_thread() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "identity",
+        Sizes::new(1, 1, 1, 1),
+        vec![],
+        vec![
+            Instruction::Load(0),
+            Instruction::Ret(1),
+        ],
+    );
+
+    // An interrupt that lands between runs does not affect the next run.
+    let mut context = Context::new(1024);
+    let handle = context.interrupt_handle();
+    thread::spawn(move || handle.interrupt()).join().unwrap();
+    assert_eq!(context.run_raw(function, &vec![7]).unwrap(), vec![7]);
+    assert!(!context.interrupt_handle().is_interrupted());
+
+    // A suspended execution is interrupted when it is resumed.
+    context.start(function, &vec![42]).unwrap();
+    let handle = context.interrupt_handle();
+    thread::spawn(move || handle.interrupt()).join().unwrap();
+    match context.resume(function) {
+        Err(Fault { error: VmError::Interrupted, .. }) => { },
+        result => panic!("Expected an interrupt, but got {:?}.", result),
+    }
+    assert!(!context.interrupt_handle().is_interrupted());
+    assert_eq!(context.resume(function).unwrap(), vec![42]);
+}
+
+#[test]
+fn interrupt_stops_running_functions() {
+    // Counts to 20000 in local 0 in straight-line code.
+    let mut insts = Vec::new();
+    for _ in 0..20000 {
+        insts.extend_from_slice(&[
+            Instruction::Load(0),
+            Instruction::Cst(0),
+            Instruction::Add(Type::U64),
+            Instruction::Store(0),
+        ]);
+    }
+    insts.extend_from_slice(&[Instruction::Load(0), Instruction::Ret(1)]);
+    let function = Function::new(
+        "count".to_string(),
+        Sizes::new(1, 1, 1, 2),
+        Arc::new(ConstantTable::new(vec![
+            Constant::U64(1),
+        ])),
+        Instructions::Bytecode(insts),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    let function = environment.fetch_function_by_id(id).unwrap();
+
+    for setup in 0..4 {
+        let mut context = Context::new(1024);
+        match setup {
+            0 => context.set_engine(Engine::Switch),
+            1 => context.set_engine(Engine::Threaded),
+            2 => context.set_fuel(u64::max_value()),
+            _ => context.set_checked(true),
+        }
+
+        // Interrupts repeatedly, since an interrupt that lands between runs
+        // is discarded when the next run starts.
+        let handle = context.interrupt_handle();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let interrupter = {
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(1));
+                    handle.interrupt();
+                }
+            })
+        };
+
+        // Runs until an interrupt lands between two instructions. Interrupted
+        // runs are resumed, and count the same as uninterrupted ones.
+        let mut stopped_within = false;
+        for _ in 0..10000 {
+            let mut result = context.run_raw(function, &vec![0]);
+            while let Err(Fault { error: VmError::Interrupted, .. }) = result {
+                assert!(context.is_suspended());
+                stopped_within |= context.current_inst_index().unwrap() > 0;
+                result = context.resume(function);
+            }
+            assert_eq!(result.unwrap(), vec![20000]);
+            if stopped_within {
+                break;
+            }
+        }
+        stopped.store(true, Ordering::SeqCst);
+        interrupter.join().unwrap();
+        assert!(stopped_within);
+    }
+}
+
+/// Interrupts its context before the instruction at inst_index executes.
+struct Interrupter {
+    handle: InterruptHandle,
+    inst_index: usize,
+}
+
+impl ExecutionObserver for Interrupter {
+    fn on_instruction(&mut self, _function: &Function, inst_index: usize, _inst: &Instruction) {
+        if inst_index == self.inst_index {
+            self.handle.interrupt();
+        }
+    }
+}
+
+#[test]
+fn interrupts_stop_at_the_next_instruction() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "div",
+        Sizes::new(1, 2, 2, 2),
+        vec![],
+        vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Div(Type::U64),
+            Instruction::Ret(1),
+        ],
+    );
+
+    let mut context = Context::new(1024);
+    let handle = context.interrupt_handle();
+    context.set_observer(Box::new(Interrupter { handle: handle, inst_index: 1 }));
+    match context.run_raw(function, &vec![12, 4]) {
+        Err(Fault { error: VmError::Interrupted, .. }) => { },
+        result => panic!("Expected an interrupt, but got {:?}.", result),
+    }
+    assert_eq!(context.current_inst_index(), Some(2));
+    assert_eq!(context.operand_count(), 2);
+    assert_eq!(context.resume(function).unwrap(), vec![3]);
+}
@@ -0,0 +1,275 @@
+#![cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+
+extern crate lore;
+
+mod common;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::cst::*;
+
+use common::fetched;
+
+
+#[test]
+fn jit_compiles_hot_functions() {
+    let compilable = Function::new(
+        "affine".to_string(),
+        Sizes::new(2, 2, 3, 3),
+        Arc::new(ConstantTable::new(vec![
+            Constant::I64(-3),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Mul(Type::I64),
+            Instruction::Cst(0),
+            Instruction::Add(Type::I64),
+            Instruction::Dup,
+            Instruction::Store(2),
+            Instruction::Load(2),
+            Instruction::Load(0),
+            Instruction::Sub(Type::I64),
+            Instruction::Ret(2),
+        ]),
+    );
+
+    let converting = Function::new(
+        "converting".to_string(),
+        Sizes::new(1, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Div(Type::U64),
+            Instruction::Conv(Type::U64, Type::F64),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let compilable_id = environment.register_function(compilable);
+    let converting_id = environment.register_function(converting);
+    environment.fetch_function_by_id(compilable_id).unwrap();
+    environment.fetch_function_by_id(converting_id).unwrap();
+    let compilable = environment.get_function_by_id(compilable_id);
+    let converting = environment.get_function_by_id(converting_id);
+
+    let mut context = Context::new(1024);
+    context.enable_jit(2);
+    let expected = vec![(7 * 5 - 3) as u64, (7 * 5 - 3 - 7) as u64];
+    for i in 0..3 {
+        assert_eq!(context.is_jit_compiled(compilable), i >= 2);
+        assert_eq!(context.run_raw(compilable, &vec![7, 5]).unwrap(), expected);
+        assert_eq!(context.run_raw(converting, &vec![12, 4]).unwrap(), vec![3.0f64.to_bits()]);
+    }
+    assert!(context.is_jit_compiled(compilable));
+    assert!(!context.is_jit_compiled(converting));
+    assert!(context.run_raw(converting, &vec![12, 0]).is_err());
+
+    // Native code does not canonicalize floats, so it is not used when
+    // floats are deterministic.
+    let mut deterministic = Context::new(1024);
+    deterministic.enable_jit(1);
+    deterministic.set_deterministic_floats(true);
+    for _ in 0..3 {
+        assert_eq!(deterministic.run_raw(compilable, &vec![7, 5]).unwrap(), expected);
+    }
+    assert!(!deterministic.is_jit_compiled(compilable));
+
+    // A function of another environment with the same ID and instruction
+    // count does not run the compiled code.
+    let mut other = Environment::new();
+    let other_id = other.register_function(Function::new(
+        "affine".to_string(),
+        Sizes::new(2, 2, 3, 3),
+        Arc::new(ConstantTable::new(vec![
+            Constant::I64(100),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(1),
+            Instruction::Load(0),
+            Instruction::Sub(Type::I64),
+            Instruction::Cst(0),
+            Instruction::Add(Type::I64),
+            Instruction::Dup,
+            Instruction::Store(2),
+            Instruction::Load(2),
+            Instruction::Load(0),
+            Instruction::Add(Type::I64),
+            Instruction::Ret(2),
+        ]),
+    ));
+    assert_eq!(other_id, compilable_id);
+    let foreign = other.fetch_function_by_id(other_id).unwrap();
+    assert!(!context.is_jit_compiled(foreign));
+    assert_eq!(context.run_raw(foreign, &vec![7, 5]).unwrap(), vec![98, 105]);
+
+    // The foreign function has replaced the code of the compiled one.
+    assert!(!context.is_jit_compiled(compilable));
+
+    // A compilable function that takes the ID of an unsupported one is compiled.
+    let mut replacing = Environment::new();
+    replacing.register_function(Function::new(
+        "placeholder".to_string(),
+        Sizes::new(0, 0, 0, 0),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![]),
+    ));
+    let replacing_id = replacing.register_function(Function::new(
+        "converting".to_string(),
+        Sizes::new(1, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Mul(Type::U64),
+            Instruction::Ret(1),
+        ]),
+    ));
+    assert_eq!(replacing_id, converting_id);
+    let multiplying = replacing.fetch_function_by_id(replacing_id).unwrap();
+    for _ in 0..3 {
+        assert_eq!(context.run_raw(multiplying, &vec![12, 4]).unwrap(), vec![48]);
+    }
+    assert!(context.is_jit_compiled(multiplying));
+}
+
+#[test]
+fn jit_compiles_integer_and_float_arithmetic() {
+    let operations: Vec<fn(Type) -> Instruction> = vec![
+        Instruction::Add, Instruction::Sub, Instruction::Mul,
+        Instruction::Div, Instruction::Rem, Instruction::Cmp,
+    ];
+    let f64_bits = |a: f64, b: f64| vec![a.to_bits(), b.to_bits()];
+    let f32_bits = |a: f32, b: f32| vec![a.to_bits() as u64, b.to_bits() as u64];
+    let cases = vec![
+        (Type::I32, vec![
+            vec![7i32 as u64, -3i32 as u64],
+            vec![i32::min_value() as u64, -1i32 as u64],
+            vec![i32::max_value() as u64, 2],
+            vec![5, 0],
+            // 32-bit operations ignore the high halves of raw arguments.
+            vec![0x1_0000_0009, 0xFFFF_FFFD],
+        ]),
+        (Type::U32, vec![
+            vec![u32::max_value() as u64, 2],
+            vec![3, 7],
+            vec![5, 0],
+            vec![0x1_0000_0009, 0x2_0000_0002],
+        ]),
+        (Type::I64, vec![
+            vec![i64::min_value() as u64, -1i64 as u64],
+            vec![-7i64 as u64, 2],
+            vec![i64::max_value() as u64, 3],
+            vec![5, 0],
+        ]),
+        (Type::U64, vec![
+            vec![u64::max_value(), 3],
+            vec![2, 9],
+            vec![5, 0],
+        ]),
+        (Type::F64, vec![
+            f64_bits(1.5, -0.25),
+            f64_bits(::std::f64::NAN, 1.0),
+            f64_bits(1.0, 0.0),
+            f64_bits(-0.0, 0.0),
+            f64_bits(-2.0, 3.0),
+        ]),
+        (Type::F32, vec![
+            f32_bits(1.5, -0.25),
+            f32_bits(::std::f32::NAN, 1.0),
+            f32_bits(1.0, 0.0),
+            f32_bits(-2.0, 3.0),
+        ]),
+    ];
+
+    let mut environment = Environment::new();
+    let mut ids = Vec::new();
+    for &(t, _) in &cases {
+        for operation in &operations {
+            // The interpreter continues after operations that exit native code.
+            let name = format!("arithmetic{}", ids.len());
+            ids.push(environment.register_function(Function::new(
+                name,
+                Sizes::new(2, 2, 2, 2),
+                Arc::new(ConstantTable::new(vec![])),
+                Instructions::Bytecode(vec![
+                    Instruction::Load(0),
+                    Instruction::Load(1),
+                    operation(t),
+                    Instruction::Load(1),
+                    Instruction::Ret(2),
+                ]),
+            )));
+        }
+    }
+    for &id in &ids {
+        environment.fetch_function_by_id(id).unwrap();
+    }
+
+    let mut compiled = Context::new(1024);
+    compiled.enable_jit(1);
+    let mut interpreted = Context::new(1024);
+    let mut ids = ids.into_iter();
+    for &(t, ref arguments) in &cases {
+        let float = t == Type::F64 || t == Type::F32;
+        for (o, _) in operations.iter().enumerate() {
+            let function = environment.get_function_by_id(ids.next().unwrap());
+            for arguments in arguments {
+                match (compiled.run_raw(function, arguments), interpreted.run_raw(function, arguments)) {
+                    (Ok(native), Ok(expected)) => assert_eq!(native, expected),
+                    (Err(native), Err(expected)) => {
+                        assert_eq!(format!("{:?}", native.error), format!("{:?}", expected.error));
+                        assert_eq!(native.trace.frames[0].inst_index, 2);
+                    },
+                    (native, expected) => panic!("{:?} != {:?}", native.is_ok(), expected.is_ok()),
+                }
+            }
+            // Float remainders are not compiled.
+            assert_eq!(compiled.is_jit_compiled(function), !(float && o == 4));
+        }
+    }
+}
+
+#[test]
+fn jit_code_counts_against_the_memory_limit() {
+    let mut environment = Environment::new();
+    let sum = fetched(
+        &mut environment,
+        "sum",
+        Sizes::new(1, 2, 2, 2),
+        vec![],
+        vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Add(Type::U64),
+            Instruction::Ret(1),
+        ],
+    );
+
+    // The code does not fit next to the stack, so the function is interpreted.
+    let mut context = Context::with_stack_limits(8, 8);
+    context.set_memory_limit(4096);
+    context.enable_jit(1);
+    for _ in 0..3 {
+        assert_eq!(context.run_raw(sum, &vec![2, 3]).unwrap(), vec![5]);
+    }
+    assert!(!context.is_jit_compiled(sum));
+    assert_eq!(context.memory_usage().code, 0);
+
+    // Compiled code takes a whole page.
+    context.set_memory_limit(8192);
+    assert_eq!(context.run_raw(sum, &vec![2, 3]).unwrap(), vec![5]);
+    assert!(context.is_jit_compiled(sum));
+    let usage = context.memory_usage();
+    assert_eq!(usage.code, 4096);
+    assert_eq!(usage.total(), usage.stack + usage.heap + 4096);
+
+    context.disable_jit();
+    assert_eq!(context.memory_usage().code, 0);
+}
@@ -0,0 +1,84 @@
+extern crate lore;
+extern crate num;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+
+use num::BigInt;
+
+
+#[test]
+fn memory_budget_bounds_stack_heap_and_strings() {
+    let mut squares = vec![Instruction::Cst(0)];
+    for _ in 0..8 {
+        squares.push(Instruction::Dup);
+        squares.push(Instruction::Mul(Type::BigInt));
+    }
+    squares.push(Instruction::Pop);
+
+    let mut environment = Environment::new();
+    let squares = environment.register_function(Function::new(
+        "squares".to_string(),
+        Sizes::new(0, 0, 0, 2),
+        Arc::new(ConstantTable::new(vec![
+            Constant::BigInt(BigInt::from(u64::max_value())),
+        ])),
+        Instructions::Bytecode(squares),
+    ));
+    let padded = environment.register_function(Function::new(
+        "padded".to_string(),
+        Sizes::new(0, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![
+            Constant::Str("{u64:60000}\n".to_string()),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::PrintFmt(0, 1),
+        ]),
+    ));
+    let precise = environment.register_function(Function::new(
+        "precise".to_string(),
+        Sizes::new(0, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![
+            Constant::Str("{f64:.60000}\n".to_string()),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::PrintFmt(0, 1),
+        ]),
+    ));
+    let deep = environment.register_function(Function::new(
+        "deep".to_string(),
+        Sizes::new(0, 0, 0, 200),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![]),
+    ));
+
+    let mut context = Context::with_stack_limits(4, 1 << 20);
+    assert!(context.run_raw(environment.fetch_function_by_id(squares).unwrap(), &vec![]).is_ok());
+    let usage = context.memory_usage();
+    assert_eq!(usage.stack, 4 * 8);
+    assert!(usage.heap > 2048);
+    assert_eq!(usage.total(), usage.stack + usage.heap);
+
+    context.set_memory_limit(1024);
+    assert_eq!(context.memory_limit(), Some(1024));
+    for &id in &[squares, padded, precise, deep] {
+        let arguments = vec![0; environment.get_function_by_id(id).sizes.argument_count as usize];
+        match context.run_raw(environment.fetch_function_by_id(id).unwrap(), &arguments) {
+            Err(Fault { error: VmError::OutOfMemory { .. }, .. }) => { },
+            result => panic!("Expected to run out of memory, but got {:?}.", result),
+        }
+        assert!(context.memory_usage().total() <= 1024);
+    }
+
+    context.remove_memory_limit();
+    assert!(context.run_raw(environment.fetch_function_by_id(deep).unwrap(), &vec![]).is_ok());
+    assert!(context.stack_length() >= 200);
+}
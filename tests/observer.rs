@@ -0,0 +1,78 @@
+extern crate lore;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::observer::*;
+
+use common::fetched;
+
+
+struct Tracer {
+    events: Rc<RefCell<Vec<String>>>,
+}
+
+impl ExecutionObserver for Tracer {
+    fn on_instruction(&mut self, _function: &Function, inst_index: usize, inst: &Instruction) {
+        self.events.borrow_mut().push(format!("{}: {:?}", inst_index, inst));
+    }
+
+    fn on_call(&mut self, function: &Function) {
+        self.events.borrow_mut().push(format!("call {}", function.name));
+    }
+
+    fn on_return(&mut self, function: &Function) {
+        self.events.borrow_mut().push(format!("return {}", function.name));
+    }
+
+    fn on_trap(&mut self, function: &Function, fault: &Fault) {
+        self.events.borrow_mut().push(format!("trap {}: {}", function.name, fault.error));
+    }
+}
+
+#[test]
+fn execution_observer() {
+    let mut environment = Environment::new();
+    let function = fetched(
+        &mut environment,
+        "div",
+        Sizes::new(1, 2, 2, 2),
+        vec![],
+        vec![
+            Instruction::Load(0),
+            Instruction::Load(1),
+            Instruction::Div(Type::U64),
+            Instruction::Ret(1),
+        ],
+    );
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut context = Context::new(1024);
+    context.set_observer(Box::new(Tracer { events: events.clone() }));
+
+    assert_eq!(context.run_raw(function, &vec![12, 4]).unwrap(), vec![3]);
+    assert_eq!(events.borrow().join("\n"), concat!(
+        "call div\n",
+        "0: load $0\n",
+        "1: load $1\n",
+        "2: div[u64]\n",
+        "3: ret(1)\n",
+        "return div",
+    ));
+
+    events.borrow_mut().clear();
+    assert!(context.run_raw(function, &vec![12, 0]).is_err());
+    assert_eq!(events.borrow().last().unwrap(), "trap div: Division by zero.");
+
+    assert!(context.remove_observer().is_some());
+    events.borrow_mut().clear();
+    assert_eq!(context.run_raw(function, &vec![12, 4]).unwrap(), vec![3]);
+    assert!(events.borrow().is_empty());
+}
@@ -0,0 +1,105 @@
+extern crate lore;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::cst::*;
+
+
+#[test]
+fn profiler_counts() {
+    let function = Function::new(
+        "cube".to_string(),
+        Sizes::new(1, 1, 1, 3),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Dup,
+            Instruction::Dup,
+            Instruction::Mul(Type::I32),
+            Instruction::Mul(Type::I32),
+            Instruction::Ret(1),
+        ]),
+    );
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    let function = environment.fetch_function_by_id(id).unwrap();
+
+    let mut context = Context::new(1024);
+    context.enable_profiling();
+    for _ in 0..3 {
+        context.run_raw(function, &vec![2]).unwrap();
+    }
+
+    let report = context.disable_profiling().unwrap();
+    assert_eq!(report.opcodes[0], (Opcode::Dup, 6));
+    assert_eq!(report.opcodes[1], (Opcode::Mul, 6));
+    assert_eq!(report.opcodes.len(), 4);
+    assert_eq!(report.instructions.len(), 6);
+    assert!(report.instructions.iter().all(|inst| inst.function_id == id && inst.count == 3));
+
+    let profile = &report.functions[0];
+    assert_eq!(profile.function_name, "cube");
+    assert_eq!(profile.calls, 3);
+    assert_eq!(profile.inclusive_time, profile.exclusive_time);
+    assert!(format!("{}", report).contains("'cube'"));
+
+    assert!(context.profile_report().is_none());
+}
+
+#[test]
+fn profiler_adds_callee_time_to_callers() {
+    let outer = Function::new(
+        "outer".to_string(),
+        Sizes::new(1, 1, 1, 1),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Ret(1),
+        ]),
+    );
+    let inner = Function::new(
+        "inner".to_string(),
+        Sizes::new(0, 0, 0, 0),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![Instruction::Nop; 10000]),
+    );
+
+    let mut environment = Environment::new();
+    let outer_id = environment.register_function(outer);
+    let inner_id = environment.register_function(inner);
+    environment.fetch_function_by_id(outer_id).unwrap();
+    environment.fetch_function_by_id(inner_id).unwrap();
+    let outer = environment.get_function_by_id(outer_id);
+    let inner = environment.get_function_by_id(inner_id);
+
+    // The host calls inner while outer is suspended, above its operands.
+    let mut context = Context::new(1024);
+    context.enable_profiling();
+    context.start(outer, &vec![5]).unwrap();
+    context.step(outer).unwrap();
+    assert_eq!(context.operand_count(), 1);
+    let top = context.operand_stack_top().unwrap();
+    context.call(inner, top, top).unwrap();
+    assert_eq!(context.operand_u64(0), Some(5));
+    assert_eq!(context.resume(outer).unwrap(), vec![5]);
+
+    let report = context.disable_profiling().unwrap();
+    assert_eq!(report.opcode_count(Opcode::Nop), 10000);
+    assert_eq!(report.opcode_count(Opcode::Cst), 0);
+    assert_eq!(report.instruction_count(inner_id, 9999), 1);
+    assert_eq!(report.instruction_count(outer_id, 1), 1);
+
+    let outer_profile = report.function(outer_id).unwrap();
+    let inner_profile = report.function(inner_id).unwrap();
+    assert_eq!(outer_profile.calls, 1);
+    assert_eq!(inner_profile.calls, 1);
+    assert!(inner_profile.inclusive_time > Duration::new(0, 0));
+    assert_eq!(inner_profile.inclusive_time, inner_profile.exclusive_time);
+    assert_eq!(outer_profile.inclusive_time, outer_profile.exclusive_time + inner_profile.inclusive_time);
+}
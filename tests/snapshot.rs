@@ -0,0 +1,209 @@
+extern crate lore;
+extern crate num;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::snapshot::*;
+use lore::cst::*;
+
+use num::BigInt;
+
+
+#[test]
+fn snapshots_restore_suspended_executions() {
+    fn affine(offset: u64) -> Function {
+        Function::new(
+            "affine".to_string(),
+            Sizes::new(1, 1, 1, 2),
+            Arc::new(ConstantTable::new(vec![
+                Constant::U64(3),
+                Constant::U64(offset),
+            ])),
+            Instructions::Bytecode(vec![
+                Instruction::Load(0),
+                Instruction::Cst(0),
+                Instruction::Mul(Type::U64),
+                Instruction::Cst(1),
+                Instruction::Add(Type::U64),
+                Instruction::Ret(1),
+            ]),
+        )
+    }
+
+    let bytes = {
+        let mut environment = Environment::new();
+        let id = environment.register_function(affine(7));
+        environment.fetch_function_by_id(id).unwrap();
+
+        let mut context = Context::new(1024);
+        match context.snapshot(&environment) {
+            Err(VmError::NotSuspended) => { },
+            _ => panic!("Expected no suspended execution."),
+        }
+        context.start(environment.get_function_by_id(id), &vec![10]).unwrap();
+        for _ in 0..3 {
+            context.step(environment.get_function_by_id(id)).unwrap();
+        }
+        context.snapshot(&environment).unwrap().to_bytes()
+    };
+
+    // Other IDs are assigned in the restoring environment.
+    let mut environment = Environment::new();
+    environment.register_function(Function::new(
+        "other".to_string(),
+        Sizes::new(0, 0, 0, 0),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![]),
+    ));
+    let id = environment.register_function(affine(7));
+    environment.fetch_function_by_id(id).unwrap();
+
+    let snapshot = Snapshot::from_bytes(&bytes[..]).unwrap();
+    assert_eq!(snapshot.frames[0].function_name, "affine");
+    assert_eq!(snapshot.frames[0].inst_index, 3);
+
+    let mut context = Context::with_stack_limits(1, 1024);
+    context.restore(&environment, &snapshot).unwrap();
+    assert_eq!(context.current_function_id(), Some(id));
+    assert_eq!(context.operand_u64(0), Some(30));
+    assert_eq!(context.resume(environment.get_function_by_id(id)).unwrap(), vec![37]);
+
+    // Changed bytecode is detected.
+    let mut changed = Environment::new();
+    let id = changed.register_function(affine(8));
+    changed.fetch_function_by_id(id).unwrap();
+    match context.restore(&changed, &snapshot) {
+        Err(VmError::SnapshotMismatch { ref function }) if function == "affine" => { },
+        result => panic!("Expected a snapshot mismatch, but got {:?}.", result),
+    }
+
+    // So are changed constants that stay in the constant table.
+    let fingerprint = |constant: Constant, inst: Instruction| {
+        let mut environment = Environment::new();
+        let id = environment.register_function(Function::new(
+            "constant".to_string(),
+            Sizes::new(0, 0, 0, 1),
+            Arc::new(ConstantTable::new(vec![constant])),
+            Instructions::Bytecode(vec![inst]),
+        ));
+        environment.fetch_function_by_id(id).unwrap().bytecode_fingerprint().unwrap()
+    };
+    assert!(fingerprint(Constant::Str("first".to_string()), Instruction::Abort(0))
+        != fingerprint(Constant::Str("second".to_string()), Instruction::Abort(0)));
+    assert!(fingerprint(Constant::BigInt(BigInt::from(1)), Instruction::Cst(0))
+        != fingerprint(Constant::BigInt(BigInt::from(2)), Instruction::Cst(0)));
+    assert!(fingerprint(Constant::F64(0.0), Instruction::Cst(0))
+        != fingerprint(Constant::F64(-0.0), Instruction::Cst(0)));
+
+    // Resolving and specializing instructions on fetch keeps the fingerprint.
+    let mut unfetched = Environment::new();
+    let id = unfetched.register_function(affine(7));
+    let before = unfetched.get_function_by_id(id).bytecode_fingerprint();
+    assert!(before.is_some());
+    assert_eq!(unfetched.fetch_function_by_id(id).unwrap().fingerprint, before);
+
+    // Function names have to be valid UTF-8.
+    let name_offset = bytes.windows(6).position(|window| window == b"affine").unwrap();
+    let mut garbled = bytes.clone();
+    garbled[name_offset] = 0xFF;
+    match Snapshot::from_bytes(&garbled[..]) {
+        Err(VmError::InvalidSnapshot(..)) => { },
+        _ => panic!("Expected an invalid function name to be rejected."),
+    }
+
+    match Snapshot::from_bytes(&bytes[..bytes.len() - 1]) {
+        Err(VmError::InvalidSnapshot(..)) => { },
+        _ => panic!("Expected a truncated snapshot to be invalid."),
+    }
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    match Snapshot::from_bytes(&trailing[..]) {
+        Err(VmError::InvalidSnapshot(..)) => { },
+        _ => panic!("Expected trailing bytes to be rejected."),
+    }
+
+    // A stack length of u32::MAX is rejected before anything is allocated.
+    let mut oversized = bytes[..7].to_vec();
+    oversized.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+    match Snapshot::from_bytes(&oversized[..]) {
+        Err(VmError::InvalidSnapshot(..)) => { },
+        _ => panic!("Expected an oversized length to be rejected."),
+    }
+
+    let mut overflowing = Snapshot::from_bytes(&bytes[..]).unwrap();
+    overflowing.frames[0].stack_return = usize::max_value();
+    match context.restore(&environment, &overflowing) {
+        Err(VmError::InvalidSnapshot(..)) => { },
+        result => panic!("Expected an inconsistent frame, but got {:?}.", result),
+    }
+}
+
+#[test]
+fn failed_restores_keep_suspended_executions() {
+    let mut environment = Environment::new();
+    let held = environment.register_function(Function::new(
+        "held".to_string(),
+        Sizes::new(0, 0, 0, 3),
+        Arc::new(ConstantTable::new(vec![
+            Constant::BigInt(BigInt::from(1) << 60000usize),
+            Constant::U64(5),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Cst(1),
+            Instruction::Cst(1),
+            Instruction::Cst(0),
+            Instruction::Pop,
+            Instruction::Pop,
+            Instruction::Pop,
+        ]),
+    ));
+    let affine = environment.register_function(Function::new(
+        "affine".to_string(),
+        Sizes::new(1, 1, 1, 2),
+        Arc::new(ConstantTable::new(vec![
+            Constant::U64(3),
+            Constant::U64(7),
+        ])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Cst(0),
+            Instruction::Mul(Type::U64),
+            Instruction::Cst(1),
+            Instruction::Add(Type::U64),
+            Instruction::Ret(1),
+        ]),
+    ));
+    environment.fetch_function_by_id(held).unwrap();
+    environment.fetch_function_by_id(affine).unwrap();
+
+    let snapshot = {
+        let mut context = Context::new(1024);
+        context.start(environment.get_function_by_id(held), &vec![]).unwrap();
+        for _ in 0..3 {
+            context.step(environment.get_function_by_id(held)).unwrap();
+        }
+        context.snapshot(&environment).unwrap()
+    };
+    assert!(!snapshot.big_ints.is_empty());
+
+    let mut context = Context::with_stack_limits(1, 1024);
+    context.start(environment.get_function_by_id(affine), &vec![10]).unwrap();
+    for _ in 0..3 {
+        context.step(environment.get_function_by_id(affine)).unwrap();
+    }
+
+    // The heap of the snapshot exceeds the budget, so the run is kept.
+    context.set_memory_limit(1024);
+    match context.restore(&environment, &snapshot) {
+        Err(VmError::OutOfMemory { .. }) => { },
+        result => panic!("Expected to run out of memory, but got {:?}.", result),
+    }
+    assert_eq!(context.current_function_id(), Some(affine));
+    assert_eq!(context.operand_u64(0), Some(30));
+    assert_eq!(context.resume(environment.get_function_by_id(affine)).unwrap(), vec![37]);
+}
@@ -20,11 +20,11 @@ fn inc_and_print() {
 
     let inc_and_print = Function::from_file(&mut environment, Path::new("inc_and_print")).unwrap();
     let id = environment.register_function(inc_and_print);
-    let inc_and_print_ref = environment.fetch_function_by_id(id);
+    let inc_and_print_ref = environment.fetch_function_by_id(id).unwrap();
 
-    let context = Context::new(1024);
+    let mut context = Context::new(1024);
     let arguments = vec![5];
-    let results = context.run(inc_and_print_ref, &arguments);
+    let results = context.run_raw(inc_and_print_ref, &arguments).unwrap();
 
     assert_eq!(results.len(), 1);
     assert_eq!(results[0] as i64, -400);
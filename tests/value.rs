@@ -0,0 +1,49 @@
+extern crate lore;
+
+use std::sync::Arc;
+
+use lore::bytecode::*;
+use lore::context::*;
+use lore::function::*;
+use lore::environment::*;
+use lore::error::*;
+use lore::cst::*;
+use lore::value::*;
+
+
+#[test]
+fn typed_values_are_checked_against_the_signature() {
+    let mut function = Function::new(
+        "typed".to_string(),
+        Sizes::new(2, 2, 2, 2),
+        Arc::new(ConstantTable::new(vec![])),
+        Instructions::Bytecode(vec![
+            Instruction::Load(0),
+            Instruction::Load(0),
+            Instruction::Add(Type::I32),
+            Instruction::Load(1),
+            Instruction::Conv(Type::F32, Type::F64),
+            Instruction::Ret(2),
+        ]),
+    );
+    function.signature = Some(Signature::new(vec![Type::I32, Type::F32], vec![Type::I32, Type::F64]));
+
+    let mut environment = Environment::new();
+    let id = environment.register_function(function);
+    let function = environment.fetch_function_by_id(id).unwrap();
+
+    let mut context = Context::new(1024);
+    let results = context.run(function, (-21i32, 0.5f32)).unwrap();
+    assert_eq!(results, vec![Value::I32(-42), Value::F64(0.5)]);
+    let (sum, half): (i32, f64) = FromValues::from_values(results).unwrap();
+    assert_eq!((sum, half), (-42, 0.5));
+
+    match context.run(function, (-21i64, 0.5f32)).unwrap_err().error {
+        VmError::ArgumentTypeMismatch { index: 0, expected: Type::I32, actual: Type::I64, .. } => { },
+        ref error => panic!("Expected an argument type mismatch, but got {:?}.", error),
+    }
+    match context.run(function, (1i32,)).unwrap_err().error {
+        VmError::ArgumentCountMismatch { expected: 2, actual: 1, .. } => { },
+        ref error => panic!("Expected an argument count mismatch, but got {:?}.", error),
+    }
+}